@@ -0,0 +1,38 @@
+-- Publishes every write to cells, blocks, dimensions and portalviews on the `portal_changes`
+-- channel, so that every backend instance can push the change out to its subscribers.
+--
+-- Payload is a JSON object:
+-- { "table": "cells", "op": "INSERT", "id": "...", "portal_id": "...", "portal_view_id": null }
+CREATE OR REPLACE FUNCTION notify_portal_change() RETURNS trigger AS $$
+DECLARE
+    _row jsonb;
+BEGIN
+    IF (TG_OP = 'DELETE') THEN
+        _row = to_jsonb(OLD);
+    ELSE
+        _row = to_jsonb(NEW);
+    END IF;
+
+    PERFORM pg_notify('portal_changes', jsonb_build_object(
+        'table', TG_TABLE_NAME,
+        'op', TG_OP,
+        'id', _row->'id',
+        'portal_id', _row->'portal_id',
+        'portal_view_id', _row->'portal_view_id'
+    )::text);
+
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER notify_cells_change AFTER INSERT OR UPDATE OR DELETE ON cells
+    FOR EACH ROW EXECUTE PROCEDURE notify_portal_change();
+
+CREATE TRIGGER notify_blocks_change AFTER INSERT OR UPDATE OR DELETE ON blocks
+    FOR EACH ROW EXECUTE PROCEDURE notify_portal_change();
+
+CREATE TRIGGER notify_dimensions_change AFTER INSERT OR UPDATE OR DELETE ON dimensions
+    FOR EACH ROW EXECUTE PROCEDURE notify_portal_change();
+
+CREATE TRIGGER notify_portalviews_change AFTER INSERT OR UPDATE OR DELETE ON portalviews
+    FOR EACH ROW EXECUTE PROCEDURE notify_portal_change();
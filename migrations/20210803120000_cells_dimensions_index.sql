@@ -0,0 +1,2 @@
+-- Cells are looked up by the dimensions they reference, ie. every cell of a BasicTable's grid.
+CREATE INDEX cells_dimensions_idx ON cells USING GIN (dimensions);
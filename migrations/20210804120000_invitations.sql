@@ -0,0 +1,19 @@
+-- Invitations of users that haven't signed up yet. The invitation token handed out by email is
+-- signed over the invitation's id, accepting it binds the Auth0 identity to the invited user.
+CREATE TABLE invitations (
+  id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+  user_id UUID NOT NULL,
+  portal_id UUID NOT NULL,
+  egress TEXT NOT NULL,
+  expires_at TIMESTAMPTZ NOT NULL,
+  accepted_at TIMESTAMPTZ,
+  accepted_by UUID,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  created_by UUID NOT NULL,
+  updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  updated_by UUID NOT NULL
+);
+
+CREATE INDEX invitations_user_id_idx ON invitations (user_id);
+
+SELECT sqlx_manage_updated_at('invitations');
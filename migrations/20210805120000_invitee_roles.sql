@@ -0,0 +1,6 @@
+-- The portal role users invited to an org's portals get, one per egress. Roles of other kinds
+-- don't belong to an org or egress.
+ALTER TABLE roles ADD COLUMN org_id UUID;
+ALTER TABLE roles ADD COLUMN egress TEXT;
+
+CREATE UNIQUE INDEX roles_org_id_egress_idx ON roles (org_id, egress) WHERE egress IS NOT NULL;
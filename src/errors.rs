@@ -0,0 +1,160 @@
+use std::fmt;
+use std::sync::Arc;
+
+use juniper::{graphql_value, FieldError};
+
+use crate::graphql::authorization::Permission;
+
+// Errors as the API reports them. Each variant maps to a stable `extensions.code` that clients
+// can branch on. Upstream and Internal errors are logged, and their details aren't sent to the
+// client.
+//
+// NOTE: ApiError purposely doesn't implement Display. juniper has a blanket
+//       From<T: Display> for FieldError, which would clash with the conversion below.
+//
+// Upstream and Internal hold an Arc so errors shared between dataloader values classify the same.
+#[derive(Debug, Clone)]
+pub enum ApiError {
+  NotFound(String),
+
+  Forbidden(Permission),
+
+  Validation(String),
+
+  Conflict(String),
+
+  // Auth0, SES and anything else we call out to.
+  Upstream(Arc<anyhow::Error>),
+
+  Internal(Arc<anyhow::Error>),
+}
+
+impl ApiError {
+  // For raising an ApiError from code that returns anyhow::Result, ie. the db layer.
+  pub fn raise(self) -> anyhow::Error {
+    anyhow::Error::new(Raised(self))
+  }
+
+  pub fn code(&self) -> &'static str {
+    match self {
+      ApiError::NotFound(_) => "NOT_FOUND",
+      ApiError::Forbidden(_) => "FORBIDDEN",
+      ApiError::Validation(_) => "VALIDATION",
+      ApiError::Conflict(_) => "CONFLICT",
+      ApiError::Upstream(_) => "UPSTREAM",
+      ApiError::Internal(_) => "INTERNAL",
+    }
+  }
+}
+
+// Carries a raised ApiError inside an anyhow::Error.
+#[derive(Debug)]
+struct Raised(ApiError);
+
+impl fmt::Display for Raised {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match &self.0 {
+      ApiError::NotFound(msg) | ApiError::Validation(msg) | ApiError::Conflict(msg) => {
+        write!(f, "{}", msg)
+      }
+      ApiError::Forbidden(permission) => {
+        write!(f, "Missing the {} permission", permission.to_string())
+      }
+      ApiError::Upstream(err) | ApiError::Internal(err) => write!(f, "{}", err),
+    }
+  }
+}
+
+impl std::error::Error for Raised {}
+
+impl From<anyhow::Error> for ApiError {
+  fn from(err: anyhow::Error) -> Self {
+    ApiError::from(Arc::new(err))
+  }
+}
+
+impl From<Arc<anyhow::Error>> for ApiError {
+  fn from(err: Arc<anyhow::Error>) -> Self {
+    if let Some(Raised(api_error)) = err.downcast_ref::<Raised>() {
+      return api_error.clone();
+    }
+
+    if let Some(sqlx_err) = err.downcast_ref::<sqlx::Error>() {
+      match sqlx_err {
+        sqlx::Error::RowNotFound => return ApiError::NotFound("Not found".to_string()),
+        sqlx::Error::Database(db_err) => match db_err
+          .code()
+          .as_deref()
+        {
+          // unique_violation
+          Some("23505") => return ApiError::Conflict("Already exists".to_string()),
+          // foreign_key_violation
+          Some("23503") => {
+            return ApiError::Conflict(
+              "Still referenced, or references something missing".to_string(),
+            )
+          }
+          // invalid_text_representation, check_violation
+          Some("22P02") | Some("23514") => {
+            return ApiError::Validation("Invalid value".to_string())
+          }
+          _ => (),
+        },
+        _ => (),
+      }
+    }
+
+    // Only ever comes from parsing client supplied json.
+    if let Some(json_err) = err.downcast_ref::<serde_json::Error>() {
+      return ApiError::Validation(format!("Invalid JSON: {}", json_err));
+    }
+
+    if err.is::<reqwest::Error>() {
+      return ApiError::Upstream(err);
+    }
+
+    ApiError::Internal(err)
+  }
+}
+
+impl From<serde_json::Error> for ApiError {
+  fn from(err: serde_json::Error) -> Self {
+    ApiError::Validation(format!("Invalid JSON: {}", err))
+  }
+}
+
+impl From<ApiError> for FieldError {
+  fn from(err: ApiError) -> Self {
+    let code = err.code();
+
+    match err {
+      ApiError::NotFound(msg) | ApiError::Validation(msg) | ApiError::Conflict(msg) => {
+        FieldError::new(msg, graphql_value!({ "code": code }))
+      }
+      ApiError::Forbidden(permission) => {
+        let permission = permission.to_string();
+
+        FieldError::new(
+          format!("Forbidden: missing the {} permission", permission),
+          graphql_value!({ "code": code, "permission": permission }),
+        )
+      }
+      ApiError::Upstream(err) => {
+        println!("Upstream error: {:?}", err);
+        FieldError::new(
+          "An upstream service failed",
+          graphql_value!({ "code": code }),
+        )
+      }
+      ApiError::Internal(err) => {
+        println!("Internal error: {:?}", err);
+        FieldError::new("Internal server error", graphql_value!({ "code": code }))
+      }
+    }
+  }
+}
+
+// For map_err on db layer and loader results in resolvers.
+pub fn field_error<E: Into<ApiError>>(err: E) -> FieldError {
+  err.into().into()
+}
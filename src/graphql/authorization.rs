@@ -0,0 +1,96 @@
+use juniper::FieldResult;
+use strum_macros::ToString;
+
+use crate::errors::{field_error, ApiError};
+use crate::graphql::context::GQLContext;
+use crate::graphql::schema::role::RolePerms;
+use crate::services::db::DB;
+
+// Every permission flag found in SystemPermissions, OrgPermissions and PortalPermissions.
+// Serialized to the same snake_case name as the flag in the role's perms json.
+#[derive(Debug, Clone, Copy, PartialEq, ToString)]
+#[strum(serialize_all = "snake_case")]
+pub enum Permission {
+  // System and Org
+  CreateOrg,
+  ViewOrg,
+  EditOrg,
+  DeleteOrg,
+
+  // Org
+  CreatePortal,
+  ViewAllPortals,
+  ViewMemberPortals,
+  AddOrgUser,
+  DeleteOrgUser,
+  EditOrgUsers,
+
+  // Portal
+  ViewPortal,
+  EditPortal,
+}
+
+impl GQLContext {
+  // The requesting user's role perms are looked up once per request, and reused for every
+  // resolver after that.
+  async fn role_perms(&self) -> FieldResult<Vec<RolePerms>> {
+    let mut cached = self
+      .role_perms
+      .lock()
+      .await;
+
+    if let Some(role_perms) = cached.as_ref() {
+      return Ok(role_perms.clone());
+    }
+
+    let role_perms = load_role_perms(&self.db, &self.auth0_user_id).await?;
+
+    *cached = Some(role_perms.clone());
+
+    Ok(role_perms)
+  }
+
+  pub async fn authorize(&self, permission: Permission) -> FieldResult<()> {
+    let role_perms = self
+      .role_perms()
+      .await?;
+
+    check_permission(&role_perms, permission)
+  }
+}
+
+pub async fn load_role_perms(db: &DB, auth0_user_id: &str) -> FieldResult<Vec<RolePerms>> {
+  let user = db
+    .get_user_by_auth0_id(auth0_user_id)
+    .await
+    .map_err(field_error)?;
+
+  let db_roles = db
+    .get_roles(&user.role_ids)
+    .await
+    .map_err(field_error)?;
+
+  // A role that can't be read doesn't grant anything.
+  let role_perms = db_roles
+    .into_iter()
+    .filter_map(|db_role| {
+      RolePerms::from_db_role(&db_role)
+        .map_err(|err| println!("Unable to read perms for role {}: {}", db_role.id, err))
+        .ok()
+    })
+    .collect();
+
+  Ok(role_perms)
+}
+
+pub fn check_permission(role_perms: &[RolePerms], permission: Permission) -> FieldResult<()> {
+  let granted = role_perms
+    .iter()
+    .any(|perms| perms.grants(permission));
+
+  if granted {
+    Ok(())
+  } else {
+    Err(ApiError::Forbidden(permission).into())
+  }
+}
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::sync::Arc;
 
 use crate::services::db::DB;
@@ -5,20 +6,61 @@ use futures::lock::Mutex;
 use juniper;
 use sqlx::PgPool;
 
+use crate::graphql::loaders::cell_loader::{get_cell_loader, CellLoader};
+use crate::graphql::loaders::dimension_loader::{get_dimension_loader, DimensionLoader};
 use crate::graphql::loaders::org_loader::{get_org_loader, OrgLoader};
+use crate::graphql::loaders::portal_loader::{get_portal_loader, PortalLoader};
+use crate::graphql::loaders::portalview_loader::{get_portal_view_loader, PortalViewLoader};
+use crate::graphql::loaders::user_loader::{get_user_loader, UserLoader};
+use crate::graphql::schema::role::RolePerms;
+use crate::middleware::auth::Claims;
 use crate::services::auth0_service::Auth0Service;
+use crate::services::change_broker::ChangeBroker;
+use crate::services::email_service::EmailService;
+use crate::services::formula_service::FormulaGraphs;
+use crate::services::invitation_token_service::InvitationTokens;
+use crate::services::layout_service::LayoutConfig;
 
 pub struct GQLContext {
   pub pool: PgPool,
-  // Auth related. Might be nice to have the full token here.
+  // Auth related.
   pub auth0_user_id: String,
 
+  // Verified claims of the request's access token.
+  pub claims: Claims,
+
   pub db: DB,
 
   // Dataloaders
   pub org_loader: OrgLoader,
 
+  pub user_loader: UserLoader,
+
+  pub portal_loader: PortalLoader,
+
+  pub portal_view_loader: PortalViewLoader,
+
+  pub dimension_loader: DimensionLoader,
+
+  pub cell_loader: CellLoader,
+
   pub auth0_api: Arc<Arc<Mutex<Auth0Service>>>,
+
+  // Subscriptions pick up changes from here, it is fed by the database change feed.
+  pub changes: Arc<ChangeBroker>,
+
+  // Grid and overlap rules that block writes are laid out with.
+  pub layout: Arc<LayoutConfig>,
+
+  pub email: Arc<dyn EmailService>,
+
+  pub invitation_tokens: Arc<InvitationTokens>,
+
+  // Perms of the requesting user's roles, loaded the first time a resolver is authorized.
+  pub role_perms: Arc<Mutex<Option<Vec<RolePerms>>>>,
+
+  // Cells that formulas depend on, shared by every formula of the request.
+  pub formula_graphs: Arc<FormulaGraphs>,
 }
 
 impl juniper::Context for GQLContext {}
@@ -26,17 +68,42 @@ impl juniper::Context for GQLContext {}
 impl GQLContext {
   pub fn new(
     pool: PgPool,
-    auth0_user_id: String,
+    claims: Claims,
     auth0_api: Arc<Arc<Mutex<Auth0Service>>>,
+    changes: Arc<ChangeBroker>,
+    layout: Arc<LayoutConfig>,
+    email: Arc<dyn EmailService>,
+    invitation_tokens: Arc<InvitationTokens>,
   ) -> Self {
     let db = DB::new(pool.clone());
+    let auth0_user_id = claims
+      .sub
+      .clone();
 
     GQLContext {
       pool: pool.clone(),
-      auth0_user_id,
+      auth0_user_id: auth0_user_id.clone(),
+      claims,
       db: db.clone(),
-      org_loader: get_org_loader(db),
+      org_loader: get_org_loader(db.clone()),
+      user_loader: get_user_loader(db.clone(), auth0_user_id.clone()),
+      portal_loader: get_portal_loader(db.clone(), auth0_user_id.clone()),
+      portal_view_loader: get_portal_view_loader(db.clone(), auth0_user_id.clone()),
+      dimension_loader: get_dimension_loader(db.clone(), auth0_user_id.clone()),
+      cell_loader: get_cell_loader(db, auth0_user_id),
       auth0_api,
+      changes,
+      layout,
+      email,
+      invitation_tokens,
+      role_perms: Arc::new(Mutex::new(None)),
+      formula_graphs: Arc::new(Mutex::new(HashMap::new())),
     }
   }
+
+  pub fn has_scope(&self, scope: &str) -> bool {
+    self
+      .claims
+      .has_scope(scope)
+  }
 }
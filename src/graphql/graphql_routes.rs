@@ -1,4 +1,5 @@
 use std::sync::Arc;
+use std::time::Duration;
 
 use crate::services::auth0_service::Auth0Service;
 use crate::state::State;
@@ -6,12 +7,17 @@ use actix_web::{dev, web, Error, HttpResponse};
 
 use actix_web_httpauth::middleware::HttpAuthentication;
 use futures::lock::Mutex;
+use juniper_graphql_ws::ConnectionConfig;
 
 use super::context::GQLContext;
+use super::juniper_actix::subscriptions::subscriptions_handler;
 use super::juniper_actix::{graphql_handler, playground_handler};
 use super::schema::Schema;
-use crate::middleware::auth::validator;
-use crate::models::user::Auth0UserId;
+use crate::middleware::auth::{validator, Claims};
+use crate::services::change_broker::ChangeBroker;
+use crate::services::email_service::EmailService;
+use crate::services::invitation_token_service::InvitationTokens;
+use crate::services::layout_service::LayoutConfig;
 
 // async fn get_decoded_token()
 
@@ -23,12 +29,25 @@ async fn graphql_route(
   schema: web::Data<Schema>,
   state: web::Data<State>,
   auth0_api: web::Data<Arc<Mutex<Auth0Service>>>,
-  auth0_user_id: Auth0UserId,
+  changes: web::Data<ChangeBroker>,
+  layout: web::Data<LayoutConfig>,
+  email: web::Data<Arc<dyn EmailService>>,
+  invitation_tokens: web::Data<InvitationTokens>,
+  claims: Claims,
 ) -> Result<HttpResponse, Error> {
   let p = state.pool.clone();
   let a = auth0_api.into_inner();
+  let e = Arc::clone(email.get_ref());
 
-  let ctx = GQLContext::new(p, auth0_user_id.id, a);
+  let ctx = GQLContext::new(
+    p,
+    claims,
+    a,
+    changes.into_inner(),
+    layout.into_inner(),
+    e,
+    invitation_tokens.into_inner(),
+  );
 
   graphql_handler(schema.get_ref(), &ctx, req, payload).await
 }
@@ -40,10 +59,49 @@ pub fn get_graphql_routes() -> impl dev::HttpServiceFactory + 'static {
     .route(web::post().to(graphql_route))
 }
 
+// GraphQL Subscriptions
+
+async fn subscriptions_route(
+  req: actix_web::HttpRequest,
+  payload: actix_web::web::Payload,
+  schema: web::Data<Schema>,
+  state: web::Data<State>,
+  auth0_api: web::Data<Arc<Mutex<Auth0Service>>>,
+  changes: web::Data<ChangeBroker>,
+  layout: web::Data<LayoutConfig>,
+  email: web::Data<Arc<dyn EmailService>>,
+  invitation_tokens: web::Data<InvitationTokens>,
+  claims: Claims,
+) -> Result<HttpResponse, Error> {
+  let p = state.pool.clone();
+  let a = auth0_api.into_inner();
+  let e = Arc::clone(email.get_ref());
+
+  // The connection is authenticated once, when the websocket is opened.
+  let ctx = GQLContext::new(
+    p,
+    claims,
+    a,
+    changes.into_inner(),
+    layout.into_inner(),
+    e,
+    invitation_tokens.into_inner(),
+  );
+  let config = ConnectionConfig::new(ctx).with_keep_alive_interval(Duration::from_secs(15));
+
+  subscriptions_handler(req, payload, schema.into_inner(), config).await
+}
+
+pub fn get_graphql_subscription_routes() -> impl dev::HttpServiceFactory + 'static {
+  web::resource("/graphql/subscriptions")
+    .wrap(HttpAuthentication::bearer(validator))
+    .route(web::get().to(subscriptions_route))
+}
+
 // GraphQL Playground
 
 pub async fn playground_route() -> Result<HttpResponse, Error> {
-  playground_handler("/graphql", None).await
+  playground_handler("/graphql", Some("/graphql/subscriptions")).await
 }
 
 pub fn get_graphql_dev_routes() -> impl dev::HttpServiceFactory + 'static {
@@ -203,7 +203,6 @@ pub async fn playground_handler(
 /// *Note: this implementation is in an alpha state.*
 ///
 /// [1]: https://github.com/apollographql/subscriptions-transport-ws/blob/master/PROTOCOL.md
-#[cfg(feature = "subscriptions")]
 pub mod subscriptions {
     use std::{fmt, sync::Arc};
 
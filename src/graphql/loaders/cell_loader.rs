@@ -0,0 +1,37 @@
+use crate::services::db::cell_service::DBCell;
+use crate::services::db::DB;
+use async_trait::async_trait;
+use dataloader::non_cached::Loader;
+use dataloader::BatchFn;
+use std::collections::HashMap;
+
+use uuid::Uuid;
+
+use super::{batch_results, LoaderResult};
+
+use crate::graphql::schema::cell::Cell;
+
+// Only loads what the requesting user can see, anything else comes back as not found.
+pub struct CellBatcher {
+  db: DB,
+
+  auth0_user_id: String,
+}
+
+#[async_trait]
+impl BatchFn<Uuid, LoaderResult<Cell>> for CellBatcher {
+  async fn load(&mut self, ids: &[Uuid]) -> HashMap<Uuid, LoaderResult<Cell>> {
+    let rows = self
+      .db
+      .get_auth0_user_cells_by_ids(&self.auth0_user_id, ids)
+      .await;
+
+    batch_results("Cell", ids, rows, |cell: &DBCell| cell.id)
+  }
+}
+
+pub type CellLoader = Loader<Uuid, LoaderResult<Cell>, CellBatcher>;
+
+pub fn get_cell_loader(db: DB, auth0_user_id: String) -> CellLoader {
+  Loader::new(CellBatcher { db, auth0_user_id }).with_yield_count(20)
+}
@@ -0,0 +1,39 @@
+use crate::services::db::dimension_service::DBDimension;
+use crate::services::db::DB;
+use async_trait::async_trait;
+use dataloader::non_cached::Loader;
+use dataloader::BatchFn;
+use std::collections::HashMap;
+
+use uuid::Uuid;
+
+use super::{batch_results, LoaderResult};
+
+use crate::graphql::schema::dimension::Dimension;
+
+// Only loads what the requesting user can see, anything else comes back as not found.
+pub struct DimensionBatcher {
+  db: DB,
+
+  auth0_user_id: String,
+}
+
+#[async_trait]
+impl BatchFn<Uuid, LoaderResult<Dimension>> for DimensionBatcher {
+  async fn load(&mut self, ids: &[Uuid]) -> HashMap<Uuid, LoaderResult<Dimension>> {
+    let rows = self
+      .db
+      .get_auth0_user_dimensions_by_ids(&self.auth0_user_id, ids)
+      .await;
+
+    batch_results("Dimension", ids, rows, |dimension: &DBDimension| {
+      dimension.id
+    })
+  }
+}
+
+pub type DimensionLoader = Loader<Uuid, LoaderResult<Dimension>, DimensionBatcher>;
+
+pub fn get_dimension_loader(db: DB, auth0_user_id: String) -> DimensionLoader {
+  Loader::new(DimensionBatcher { db, auth0_user_id }).with_yield_count(20)
+}
@@ -1 +1,83 @@
-pub mod org_loader;
\ No newline at end of file
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use juniper::FieldResult;
+use uuid::Uuid;
+
+use crate::errors::{field_error, ApiError};
+
+pub mod cell_loader;
+pub mod dimension_loader;
+pub mod org_loader;
+pub mod portal_loader;
+pub mod portalview_loader;
+pub mod user_loader;
+
+// Loader values have to be Clone, hence the Arc around the error.
+pub type LoaderResult<T> = Result<T, Arc<anyhow::Error>>;
+
+// Turns the rows of a batch query into loader values. Every requested id gets a value, the
+// loader panics on missing keys, so ids without a row become not found errors, rows that can't
+// be converted become errors for their id, and a failed query becomes an error for every id in
+// the batch.
+pub fn batch_results<D, T>(
+  entity: &str,
+  ids: &[Uuid],
+  rows: anyhow::Result<Vec<D>>,
+  id_of: impl Fn(&D) -> Uuid,
+) -> HashMap<Uuid, LoaderResult<T>>
+where
+  T: TryFrom<D>,
+  T::Error: Into<anyhow::Error>,
+{
+  match rows {
+    Ok(rows) => {
+      let mut results = rows
+        .into_iter()
+        .map(|row| {
+          let id = id_of(&row);
+
+          (id, T::try_from(row).map_err(|err| Arc::new(err.into())))
+        })
+        .collect::<HashMap<Uuid, LoaderResult<T>>>();
+
+      for id in ids {
+        results
+          .entry(*id)
+          .or_insert_with(|| {
+            Err(Arc::new(
+              ApiError::NotFound(format!("{} {} not found", entity, id)).raise(),
+            ))
+          });
+      }
+
+      results
+    }
+    Err(err) => {
+      let err = Arc::new(err);
+
+      ids
+        .iter()
+        .map(|id| (*id, Err(err.clone())))
+        .collect()
+    }
+  }
+}
+
+// Values of a load_many in the order of the given ids, failing if any of them failed to load.
+pub fn ordered_results<T>(
+  ids: &[Uuid],
+  mut results: HashMap<Uuid, LoaderResult<T>>,
+) -> FieldResult<Vec<T>> {
+  ids
+    .iter()
+    .map(|id| {
+      results
+        .remove(id)
+        .unwrap_or_else(|| Err(Arc::new(anyhow!("{} not loaded", id))))
+        .map_err(field_error)
+    })
+    .collect()
+}
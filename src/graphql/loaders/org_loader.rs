@@ -7,6 +7,8 @@ use std::collections::HashMap;
 
 use uuid::Uuid;
 
+use super::{batch_results, LoaderResult};
+
 use crate::graphql::schema::org::Org;
 
 pub struct OrgBatcher {
@@ -14,32 +16,18 @@ pub struct OrgBatcher {
 }
 
 #[async_trait]
-impl BatchFn<Uuid, Org> for OrgBatcher {
-  // async fn load(&mut self, ids: &[Uuid]) -> HashMap<Uuid, Org> {
-  async fn load(&mut self, ids: &[Uuid]) -> HashMap<Uuid, Org> {
-    // Question: How do I handle DB errors? HashMap<Uuid, Result<Org, Error>>?
-    let orgs = self
+impl BatchFn<Uuid, LoaderResult<Org>> for OrgBatcher {
+  async fn load(&mut self, ids: &[Uuid]) -> HashMap<Uuid, LoaderResult<Org>> {
+    let rows = self
       .db
       .get_orgs(ids)
-      .await
-      .map(|orgs| -> HashMap<Uuid, Org> {
-        orgs
-          .into_iter()
-          .fold(HashMap::<Uuid, Org>::new(), |mut acc, org| {
-            let o: Org = org.into();
-            let k = o.id.clone();
-            acc.insert(k, o);
-
-            acc
-          })
-      })
-      .unwrap();
-
-    orgs
+      .await;
+
+    batch_results("Org", ids, rows, |org: &DBOrg| org.id)
   }
 }
 
-pub type OrgLoader = Loader<Uuid, Org, OrgBatcher>;
+pub type OrgLoader = Loader<Uuid, LoaderResult<Org>, OrgBatcher>;
 
 // To create a new loader
 pub fn get_org_loader(db: DB) -> OrgLoader {
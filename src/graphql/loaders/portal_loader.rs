@@ -0,0 +1,37 @@
+use crate::services::db::portal_service::DBPortal;
+use crate::services::db::DB;
+use async_trait::async_trait;
+use dataloader::non_cached::Loader;
+use dataloader::BatchFn;
+use std::collections::HashMap;
+
+use uuid::Uuid;
+
+use super::{batch_results, LoaderResult};
+
+use crate::graphql::schema::portal::Portal;
+
+// Only loads what the requesting user can see, anything else comes back as not found.
+pub struct PortalBatcher {
+  db: DB,
+
+  auth0_user_id: String,
+}
+
+#[async_trait]
+impl BatchFn<Uuid, LoaderResult<Portal>> for PortalBatcher {
+  async fn load(&mut self, ids: &[Uuid]) -> HashMap<Uuid, LoaderResult<Portal>> {
+    let rows = self
+      .db
+      .get_auth0_user_portals_by_ids(&self.auth0_user_id, ids)
+      .await;
+
+    batch_results("Portal", ids, rows, |portal: &DBPortal| portal.id)
+  }
+}
+
+pub type PortalLoader = Loader<Uuid, LoaderResult<Portal>, PortalBatcher>;
+
+pub fn get_portal_loader(db: DB, auth0_user_id: String) -> PortalLoader {
+  Loader::new(PortalBatcher { db, auth0_user_id }).with_yield_count(20)
+}
@@ -0,0 +1,39 @@
+use crate::services::db::portalview_service::DBPortalView;
+use crate::services::db::DB;
+use async_trait::async_trait;
+use dataloader::non_cached::Loader;
+use dataloader::BatchFn;
+use std::collections::HashMap;
+
+use uuid::Uuid;
+
+use super::{batch_results, LoaderResult};
+
+use crate::graphql::schema::portalview::PortalView;
+
+// Only loads what the requesting user can see, anything else comes back as not found.
+pub struct PortalViewBatcher {
+  db: DB,
+
+  auth0_user_id: String,
+}
+
+#[async_trait]
+impl BatchFn<Uuid, LoaderResult<PortalView>> for PortalViewBatcher {
+  async fn load(&mut self, ids: &[Uuid]) -> HashMap<Uuid, LoaderResult<PortalView>> {
+    let rows = self
+      .db
+      .get_auth0_user_portal_views_by_ids(&self.auth0_user_id, ids)
+      .await;
+
+    batch_results("Portal view", ids, rows, |portal_view: &DBPortalView| {
+      portal_view.id
+    })
+  }
+}
+
+pub type PortalViewLoader = Loader<Uuid, LoaderResult<PortalView>, PortalViewBatcher>;
+
+pub fn get_portal_view_loader(db: DB, auth0_user_id: String) -> PortalViewLoader {
+  Loader::new(PortalViewBatcher { db, auth0_user_id }).with_yield_count(20)
+}
@@ -0,0 +1,37 @@
+use crate::services::db::user_service::DBUser;
+use crate::services::db::DB;
+use async_trait::async_trait;
+use dataloader::non_cached::Loader;
+use dataloader::BatchFn;
+use std::collections::HashMap;
+
+use uuid::Uuid;
+
+use super::{batch_results, LoaderResult};
+
+use crate::graphql::schema::user::User;
+
+// Only loads what the requesting user can see, anything else comes back as not found.
+pub struct UserBatcher {
+  db: DB,
+
+  auth0_user_id: String,
+}
+
+#[async_trait]
+impl BatchFn<Uuid, LoaderResult<User>> for UserBatcher {
+  async fn load(&mut self, ids: &[Uuid]) -> HashMap<Uuid, LoaderResult<User>> {
+    let rows = self
+      .db
+      .get_auth0_user_visible_users(&self.auth0_user_id, ids)
+      .await;
+
+    batch_results("User", ids, rows, |user: &DBUser| user.id)
+  }
+}
+
+pub type UserLoader = Loader<Uuid, LoaderResult<User>, UserBatcher>;
+
+pub fn get_user_loader(db: DB, auth0_user_id: String) -> UserLoader {
+  Loader::new(UserBatcher { db, auth0_user_id }).with_yield_count(20)
+}
@@ -2,4 +2,5 @@ pub mod juniper_actix;
 pub mod graphql_routes;
 pub mod schema;
 pub mod context;
-pub mod loaders;
\ No newline at end of file
+pub mod loaders;
+pub mod authorization;
\ No newline at end of file
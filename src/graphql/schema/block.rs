@@ -1,104 +1,469 @@
 use chrono::{DateTime, Utc};
-use juniper::{FieldError, FieldResult, GraphQLEnum, GraphQLObject, GraphQLUnion};
+use juniper::{
+  graphql_object, FieldResult, GraphQLEnum, GraphQLInputObject, GraphQLObject,
+  GraphQLUnion,
+};
 use serde_json;
-use std::str::FromStr;
-use strum_macros::EnumString;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use strum_macros::{EnumString, ToString};
 use uuid::Uuid;
 
-// use super::Mutation;
+use super::cell::Cell;
+use super::conversion::{parse_column, report_decode_failure, try_from_rows};
+use super::dimension::Dimension;
+use super::portalview::{EgressTypes, PortalView};
+use super::Mutation;
 use super::Query;
+use crate::errors::field_error;
 use crate::graphql::context::GQLContext;
-use crate::services::db::block_service::DBBlock;
+use crate::graphql::loaders::ordered_results;
+use crate::services::db::block_service::{DBBlock, DBNewBlock, DBUpdateBlock};
 
-#[derive(Debug, GraphQLUnion, Serialize, Deserialize)]
+// Built from the block's jsonb by block_data_from_value, never (de)serialized itself.
+#[derive(Debug, Clone, GraphQLUnion)]
+#[graphql(context = GQLContext)]
 pub enum GQLBlocks {
   BasicTable(BasicTableBlock),
+  Text(TextBlock),
+  KeyValue(KeyValueBlock),
+  Checklist(ChecklistBlock),
+  Chart(ChartBlock),
   Empty(EmptyBlock),
 }
 
-#[derive(Debug, Serialize, Deserialize, GraphQLEnum, EnumString)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, GraphQLEnum, EnumString, ToString)]
 pub enum BlockTypes {
   BasicTable,
+  Text,
+  KeyValue,
+  Checklist,
+  Chart,
 }
 
-#[derive(GraphQLObject, Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone)]
 pub struct Block {
   pub id: Uuid,
 
-  #[serde(rename = "blockType")]
   pub block_type: BlockTypes,
 
-  #[serde(rename = "portalId")]
   pub portal_id: Uuid,
 
-  #[serde(rename = "portalViewId")]
   pub portal_view_id: Uuid,
 
-  pub egress: String,
+  pub egress: EgressTypes,
 
-  pub bbox: Vec<i32>,
+  pub bbox: BBox,
 
-  #[serde(rename = "blockData")]
   pub block_data: GQLBlocks,
 
-  #[serde(rename = "createdAt")]
   pub created_at: DateTime<Utc>,
 
-  #[serde(rename = "createdBy")]
   pub created_by: Uuid,
 
-  #[serde(rename = "updatedAt")]
   pub updated_at: DateTime<Utc>,
 
-  #[serde(rename = "updatedBy")]
   pub updated_by: Uuid,
 }
 
-impl From<DBBlock> for Block {
-  fn from(db_block: DBBlock) -> Self {
-    // let a = serde_json::to_string(&db_block).expect("blah");
-    // println!("{}", a);
+#[graphql_object(context = GQLContext)]
+impl Block {
+  fn id(&self) -> Uuid {
+    self.id
+  }
 
-    // let q = db_block.block_type;
-    // println!("qqq {}", q);
+  fn block_type(&self) -> BlockTypes {
+    self.block_type
+  }
 
-    let block_data = match db_block.block_type.as_str() {
-      "BasicTable" => {
-        let b: BasicTableBlock = serde_json::from_value(db_block.data).expect("come on");
-        GQLBlocks::BasicTable(b)
-      }
-      &_ => GQLBlocks::Empty(EmptyBlock {
-        block_type: String::from("nothing"),
-      }),
-    };
+  fn portal_id(&self) -> Uuid {
+    self.portal_id
+  }
+
+  fn portal_view_id(&self) -> Uuid {
+    self.portal_view_id
+  }
+
+  async fn portal_view(&self, ctx: &GQLContext) -> FieldResult<PortalView> {
+    ctx
+      .portal_view_loader
+      .load(self.portal_view_id)
+      .await
+      .map_err(field_error)
+  }
+
+  fn egress(&self) -> EgressTypes {
+    self.egress
+  }
 
-    let block_type = BlockTypes::from_str(db_block.block_type.as_str())
-      .expect("Unable to convert block_type string to enum variant");
+  fn bbox(&self) -> BBox {
+    self.bbox
+  }
+
+  fn block_data(&self) -> GQLBlocks {
+    self
+      .block_data
+      .clone()
+  }
+
+  fn created_at(&self) -> DateTime<Utc> {
+    self.created_at
+  }
+
+  fn created_by(&self) -> Uuid {
+    self.created_by
+  }
+
+  fn updated_at(&self) -> DateTime<Utc> {
+    self.updated_at
+  }
+
+  fn updated_by(&self) -> Uuid {
+    self.updated_by
+  }
+}
 
-    Block {
+impl TryFrom<DBBlock> for Block {
+  type Error = anyhow::Error;
+
+  fn try_from(db_block: DBBlock) -> Result<Self, Self::Error> {
+    let block_type: BlockTypes =
+      parse_column("Block", db_block.id, "block_type", &db_block.block_type)?;
+
+    let egress = parse_column("Block", db_block.id, "egress", &db_block.egress)?;
+
+    let block_data = block_data_from_value(block_type, db_block.portal_id, db_block.data)
+      .unwrap_or_else(|err| {
+        report_decode_failure("Block", db_block.id, err);
+
+        GQLBlocks::Empty(EmptyBlock {
+          block_type: block_type.to_string(),
+        })
+      });
+
+    // Blocks written before bboxes were validated can hold anything.
+    let bbox = BBox::from_db(&db_block.bbox).unwrap_or_else(|| {
+      report_decode_failure(
+        "Block",
+        db_block.id,
+        format!("bbox has {} values", db_block.bbox.len()),
+      );
+
+      BBox::default()
+    });
+
+    Ok(Block {
       id: db_block.id,
       block_type,
       portal_id: db_block.portal_id,
       portal_view_id: db_block.portal_view_id,
-      egress: db_block.egress,
-      bbox: db_block.bbox,
+      egress,
+      bbox,
       block_data,
       created_at: db_block.created_at,
       created_by: db_block.created_by,
       updated_at: db_block.updated_at,
       updated_by: db_block.updated_by,
+    })
+  }
+}
+
+// Position and size of a block on the grid of its portal view, in grid units.
+#[derive(GraphQLObject, Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct BBox {
+  pub x: i32,
+
+  pub y: i32,
+
+  pub w: i32,
+
+  pub h: i32,
+}
+
+impl BBox {
+  // Stored in blocks.bbox as {x, y, w, h}.
+  pub fn from_db(bbox: &[i32]) -> Option<BBox> {
+    match *bbox {
+      [x, y, w, h] => Some(BBox { x, y, w, h }),
+      _ => None,
     }
   }
+
+  pub fn to_db(&self) -> Vec<i32> {
+    vec![self.x, self.y, self.w, self.h]
+  }
+
+  // Edges saturate rather than overflow, blocks from before bboxes were validated can be anywhere.
+  pub fn right(&self) -> i32 {
+    self
+      .x
+      .saturating_add(self.w)
+  }
+
+  pub fn bottom(&self) -> i32 {
+    self
+      .y
+      .saturating_add(self.h)
+  }
+
+  pub fn overlaps(&self, other: &BBox) -> bool {
+    self.x < other.right()
+      && other.x < self.right()
+      && self.y < other.bottom()
+      && other.y < self.bottom()
+  }
 }
 
-#[derive(GraphQLObject, Debug, Serialize, Deserialize)]
-pub struct BasicTableBlock {
+#[derive(GraphQLInputObject, Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct BBoxInput {
+  pub x: i32,
+
+  pub y: i32,
+
+  pub w: i32,
+
+  pub h: i32,
+}
+
+impl From<BBoxInput> for BBox {
+  fn from(input: BBoxInput) -> Self {
+    BBox {
+      x: input.x,
+      y: input.y,
+      w: input.w,
+      h: input.h,
+    }
+  }
+}
+
+// Reads the jsonb data of a block in portal_id into the union member of its block type.
+pub fn block_data_from_value(
+  block_type: BlockTypes,
+  portal_id: Uuid,
+  data: serde_json::Value,
+) -> serde_json::Result<GQLBlocks> {
+  match block_type {
+    BlockTypes::BasicTable => serde_json::from_value(data)
+      .map(|table| GQLBlocks::BasicTable(BasicTableBlock { portal_id, table })),
+    BlockTypes::Text => serde_json::from_value(data).map(GQLBlocks::Text),
+    BlockTypes::KeyValue => serde_json::from_value(data).map(GQLBlocks::KeyValue),
+    BlockTypes::Checklist => serde_json::from_value(data).map(GQLBlocks::Checklist),
+    BlockTypes::Chart => serde_json::from_value(data).map(GQLBlocks::Chart),
+  }
+}
+
+// rows and columns are dimension ids, stored in this order in the block's jsonb.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BasicTableData {
   pub rows: Vec<Uuid>,
 
   pub columns: Vec<Uuid>,
 }
 
-#[derive(GraphQLObject, Debug, Serialize, Deserialize)]
+// The table's data, along with the portal of the block holding it. The grid only holds cells of
+// that portal.
+#[derive(Debug, Clone)]
+pub struct BasicTableBlock {
+  pub portal_id: Uuid,
+
+  pub table: BasicTableData,
+}
+
+#[graphql_object(context = GQLContext)]
+impl BasicTableBlock {
+  fn rows(&self) -> Vec<Uuid> {
+    self
+      .table
+      .rows
+      .clone()
+  }
+
+  fn columns(&self) -> Vec<Uuid> {
+    self
+      .table
+      .columns
+      .clone()
+  }
+
+  async fn row_dimensions(&self, ctx: &GQLContext) -> FieldResult<Vec<Dimension>> {
+    let rows_by_id = ctx
+      .dimension_loader
+      .load_many(self.rows())
+      .await;
+
+    ordered_results(&self.table.rows, rows_by_id)
+  }
+
+  async fn column_dimensions(&self, ctx: &GQLContext) -> FieldResult<Vec<Dimension>> {
+    let columns_by_id = ctx
+      .dimension_loader
+      .load_many(self.columns())
+      .await;
+
+    ordered_results(&self.table.columns, columns_by_id)
+  }
+
+  #[graphql(description = "The cell at each row and column, null where there is no cell")]
+  async fn grid(&self, ctx: &GQLContext) -> FieldResult<Vec<Vec<Option<Cell>>>> {
+    let table = &self.table;
+
+    let db_cells = ctx
+      .db
+      .get_cells_in_grid(self.portal_id, &table.rows, &table.columns)
+      .await
+      .map_err(field_error)?;
+
+    let cells: Vec<Cell> = try_from_rows(db_cells).map_err(field_error)?;
+
+    let row_index = index_by_id(&table.rows);
+    let column_index = index_by_id(&table.columns);
+
+    let mut grid = vec![vec![None; table.columns.len()]; table.rows.len()];
+
+    for cell in cells {
+      let row = cell
+        .dimension_ids
+        .iter()
+        .find_map(|id| row_index.get(id));
+
+      let column = cell
+        .dimension_ids
+        .iter()
+        .find_map(|id| column_index.get(id));
+
+      if let (Some(&row), Some(&column)) = (row, column) {
+        grid[row][column] = Some(cell);
+      }
+    }
+
+    Ok(grid)
+  }
+}
+
+fn index_by_id(ids: &[Uuid]) -> HashMap<Uuid, usize> {
+  ids
+    .iter()
+    .enumerate()
+    .map(|(index, id)| (*id, index))
+    .collect()
+}
+
+#[derive(GraphQLObject, Debug, Clone, Serialize, Deserialize)]
+pub struct TextBlock {
+  pub markdown: String,
+}
+
+#[derive(GraphQLObject, Debug, Clone, Serialize, Deserialize)]
+#[graphql(context = GQLContext)]
+pub struct KeyValueBlock {
+  pub pairs: Vec<KeyValuePair>,
+}
+
+// A dimension as the key, and the cell holding its value. The cell is optional so that a key
+// can be laid out before it has a value.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyValuePair {
+  #[serde(rename = "dimensionId")]
+  pub dimension_id: Uuid,
+
+  #[serde(rename = "cellId")]
+  pub cell_id: Option<Uuid>,
+}
+
+#[graphql_object(context = GQLContext)]
+impl KeyValuePair {
+  fn dimension_id(&self) -> Uuid {
+    self.dimension_id
+  }
+
+  fn cell_id(&self) -> Option<Uuid> {
+    self.cell_id
+  }
+
+  async fn dimension(&self, ctx: &GQLContext) -> FieldResult<Dimension> {
+    ctx
+      .dimension_loader
+      .load(self.dimension_id)
+      .await
+      .map_err(field_error)
+  }
+
+  async fn cell(&self, ctx: &GQLContext) -> FieldResult<Option<Cell>> {
+    match self.cell_id {
+      Some(cell_id) => ctx
+        .cell_loader
+        .load(cell_id)
+        .await
+        .map(Some)
+        .map_err(field_error),
+      None => Ok(None),
+    }
+  }
+}
+
+#[derive(GraphQLObject, Debug, Clone, Serialize, Deserialize)]
+pub struct ChecklistBlock {
+  pub items: Vec<ChecklistItem>,
+}
+
+#[derive(GraphQLObject, Debug, Clone, Serialize, Deserialize)]
+pub struct ChecklistItem {
+  pub text: String,
+
+  pub checked: bool,
+}
+
+// Plots the cells of a BasicTable block in the same portal.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChartBlock {
+  #[serde(rename = "tableBlockId")]
+  pub table_block_id: Uuid,
+
+  pub spec: ChartSpec,
+}
+
+#[graphql_object(context = GQLContext)]
+impl ChartBlock {
+  fn table_block_id(&self) -> Uuid {
+    self.table_block_id
+  }
+
+  async fn table_block(&self, ctx: &GQLContext) -> FieldResult<Block> {
+    ctx
+      .db
+      .get_auth0_user_block(&ctx.auth0_user_id, self.table_block_id)
+      .await
+      .and_then(Block::try_from)
+      .map_err(field_error)
+  }
+
+  fn spec(&self) -> ChartSpec {
+    self
+      .spec
+      .clone()
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, GraphQLEnum)]
+pub enum ChartTypes {
+  Bar,
+  Line,
+  Pie,
+}
+
+#[derive(GraphQLObject, Debug, Clone, Serialize, Deserialize)]
+pub struct ChartSpec {
+  #[serde(rename = "chartType")]
+  pub chart_type: ChartTypes,
+
+  pub title: Option<String>,
+
+  #[graphql(description = "Rows of the table to plot, all of them when empty")]
+  #[serde(default, rename = "seriesIds")]
+  pub series_ids: Vec<Uuid>,
+}
+
+#[derive(GraphQLObject, Debug, Clone, Serialize, Deserialize)]
 pub struct EmptyBlock {
   block_type: String,
 }
@@ -114,13 +479,151 @@ pub struct EmptyBlock {
 //   }
 // }
 
+#[derive(GraphQLInputObject, Debug, Serialize, Deserialize)]
+pub struct NewBlock {
+  pub block_type: BlockTypes,
+
+  pub portal_id: Uuid,
+
+  pub portal_view_id: Uuid,
+
+  pub egress: EgressTypes,
+
+  pub bbox: BBoxInput,
+
+  #[graphql(description = "JSON encoded block data, must match the shape of the block_type")]
+  pub block_data: String,
+}
+
+#[derive(GraphQLInputObject, Debug, Serialize, Deserialize)]
+pub struct UpdateBlock {
+  pub id: Uuid,
+
+  pub egress: Option<EgressTypes>,
+
+  #[graphql(description = "Other blocks of the portal view are laid out around the new bbox")]
+  pub bbox: Option<BBoxInput>,
+
+  #[graphql(
+    description = "JSON encoded block data, must match the shape of the block's block_type"
+  )]
+  pub block_data: Option<String>,
+}
+
+#[derive(GraphQLInputObject, Debug, Serialize, Deserialize)]
+pub struct BlockMove {
+  pub block_id: Uuid,
+
+  pub bbox: BBoxInput,
+}
+
 impl Query {
   pub async fn block_impl(ctx: &GQLContext, block_id: Uuid) -> FieldResult<Block> {
     ctx
       .db
-      .get_block(block_id)
+      .get_auth0_user_block(&ctx.auth0_user_id, block_id)
+      .await
+      .and_then(Block::try_from)
+      .map_err(field_error)
+  }
+
+  pub async fn blocks_impl(ctx: &GQLContext, portal_view_id: Uuid) -> FieldResult<Vec<Block>> {
+    // A portal view the user can't see is reported as not found, same as one that doesn't exist.
+    ctx
+      .db
+      .get_auth0_user_portal_view(&ctx.auth0_user_id, portal_view_id)
+      .await
+      .map_err(field_error)?;
+
+    ctx
+      .db
+      .get_blocks(portal_view_id)
+      .await
+      .and_then(try_from_rows)
+      .map_err(field_error)
+  }
+}
+
+impl Mutation {
+  pub async fn create_block_impl(ctx: &GQLContext, new_block: NewBlock) -> FieldResult<Block> {
+    let data: serde_json::Value =
+      serde_json::from_str(&new_block.block_data).map_err(field_error)?;
+
+    let db_new_block = DBNewBlock {
+      block_type: new_block
+        .block_type
+        .to_string(),
+      portal_id: new_block.portal_id,
+      portal_view_id: new_block.portal_view_id,
+      egress: new_block
+        .egress
+        .to_string(),
+      bbox: BBox::from(new_block.bbox).to_db(),
+      data,
+    };
+
+    ctx
+      .db
+      .create_block(&ctx.auth0_user_id, db_new_block, &ctx.layout)
+      .await
+      .and_then(Block::try_from)
+      .map_err(field_error)
+  }
+
+  pub async fn update_block_impl(
+    ctx: &GQLContext,
+    update_block: UpdateBlock,
+  ) -> FieldResult<Block> {
+    let data = match update_block.block_data {
+      Some(block_data) => Some(
+        serde_json::from_str::<serde_json::Value>(&block_data).map_err(field_error)?,
+      ),
+      None => None,
+    };
+
+    let db_update_block = DBUpdateBlock {
+      id: update_block.id,
+      egress: update_block
+        .egress
+        .map(|e| e.to_string()),
+      bbox: update_block
+        .bbox
+        .map(|bbox| BBox::from(bbox).to_db()),
+      data,
+    };
+
+    ctx
+      .db
+      .update_block(&ctx.auth0_user_id, db_update_block, &ctx.layout)
+      .await
+      .and_then(Block::try_from)
+      .map_err(field_error)
+  }
+
+  pub async fn move_blocks_impl(
+    ctx: &GQLContext,
+    portal_view_id: Uuid,
+    moves: Vec<BlockMove>,
+  ) -> FieldResult<Vec<Block>> {
+    let moves = moves
+      .into_iter()
+      .map(|m| (m.block_id, BBox::from(m.bbox)))
+      .collect();
+
+    ctx
+      .db
+      .move_blocks(&ctx.auth0_user_id, portal_view_id, moves, &ctx.layout)
+      .await
+      .and_then(try_from_rows)
+      .map_err(field_error)
+  }
+
+  pub async fn delete_block_impl(ctx: &GQLContext, block_id: Uuid) -> FieldResult<Block> {
+    ctx
+      .db
+      .delete_block(&ctx.auth0_user_id, block_id)
       .await
-      .map(|db_block| db_block.into())
-      .map_err(FieldError::from)
+      .and_then(Block::try_from)
+      .map_err(field_error)
   }
 }
@@ -1,30 +1,51 @@
-use std::str::FromStr;
+use std::convert::TryFrom;
 
-use chrono::{DateTime, Utc};
-use juniper::{FieldError, FieldResult, GraphQLEnum, GraphQLObject, GraphQLUnion};
-use strum_macros::EnumString;
+use chrono::{DateTime, NaiveDate, Utc};
+use juniper::{
+  graphql_object, FieldResult, GraphQLEnum, GraphQLInputObject, GraphQLObject,
+  GraphQLUnion,
+};
+use strum_macros::{EnumString, ToString};
 
-// use juniper::{GraphQLInputObject};
-
-// use super::Mutation;
+use super::conversion::{parse_column, report_decode_failure, try_from_rows};
+use super::dimension::Dimension;
+use super::Mutation;
 use super::Query;
 
+use crate::errors::field_error;
 use crate::graphql::context::GQLContext;
-use crate::services::db::cell_service::DBCell;
+use crate::graphql::loaders::ordered_results;
+use crate::services::db::cell_service::{DBCell, DBNewCell, DBUpdateCell};
+use crate::services::formula_service::evaluate_formula;
 use uuid::Uuid;
 
-#[derive(Debug, GraphQLUnion, Serialize, Deserialize)]
+#[derive(Debug, Clone, GraphQLUnion, Serialize, Deserialize)]
+#[graphql(context = GQLContext)]
 pub enum GQLCells {
   BasicText(BasicTextCell),
+  Number(NumberCell),
+  Date(DateCell),
+  Boolean(BooleanCell),
+  SingleSelect(SingleSelectCell),
+  MultiSelect(MultiSelectCell),
+  RichText(RichTextCell),
+  Formula(FormulaCell),
   Empty(EmptyCell),
 }
 
-#[derive(Debug, Serialize, Deserialize, GraphQLEnum, EnumString)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, GraphQLEnum, EnumString, ToString)]
 pub enum CellTypes {
   BasicText,
+  Number,
+  Date,
+  Boolean,
+  SingleSelect,
+  MultiSelect,
+  RichText,
+  Formula,
 }
 
-#[derive(GraphQLObject, Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Cell {
   pub id: Uuid,
 
@@ -35,7 +56,8 @@ pub struct Cell {
   #[serde(rename = "cellType")]
   pub cell_type: CellTypes,
 
-  pub dimensions: Vec<Uuid>,
+  #[serde(rename = "dimensionIds")]
+  pub dimension_ids: Vec<Uuid>,
 
   #[serde(rename = "cellData")]
   pub cell_data: GQLCells,
@@ -53,53 +75,322 @@ pub struct Cell {
   pub updated_by: Uuid,
 }
 
-impl From<DBCell> for Cell {
-  fn from(db_cell: DBCell) -> Self {
-    let cell_data = match db_cell.cell_type.as_str() {
-      "BasicText" => {
-        let c: BasicTextCell =
-          serde_json::from_value(db_cell.data).expect("Can't deserialize BasicTextCell");
-        GQLCells::BasicText(c)
-      }
-      &_ => GQLCells::Empty(EmptyCell {
-        cell_type: String::from("nothing"),
-      }),
-    };
+#[graphql_object(context = GQLContext)]
+impl Cell {
+  fn id(&self) -> Uuid {
+    self.id
+  }
+
+  fn portal_id(&self) -> Uuid {
+    self.portal_id
+  }
+
+  fn cell_type(&self) -> CellTypes {
+    self.cell_type
+  }
+
+  fn dimension_ids(&self) -> Vec<Uuid> {
+    self
+      .dimension_ids
+      .clone()
+  }
+
+  async fn dimensions(&self, ctx: &GQLContext) -> FieldResult<Vec<Dimension>> {
+    let dimensions_by_id = ctx
+      .dimension_loader
+      .load_many(
+        self
+          .dimension_ids
+          .clone(),
+      )
+      .await;
+
+    ordered_results(&self.dimension_ids, dimensions_by_id)
+  }
+
+  fn cell_data(&self) -> GQLCells {
+    self
+      .cell_data
+      .clone()
+  }
+
+  fn created_at(&self) -> DateTime<Utc> {
+    self.created_at
+  }
+
+  fn created_by(&self) -> Uuid {
+    self.created_by
+  }
+
+  fn updated_at(&self) -> DateTime<Utc> {
+    self.updated_at
+  }
+
+  fn updated_by(&self) -> Uuid {
+    self.updated_by
+  }
+}
+
+impl TryFrom<DBCell> for Cell {
+  type Error = anyhow::Error;
+
+  fn try_from(db_cell: DBCell) -> Result<Self, Self::Error> {
+    let cell_type: CellTypes = parse_column("Cell", db_cell.id, "cell_type", &db_cell.cell_type)?;
 
-    let cell_type = CellTypes::from_str(db_cell.cell_type.as_str())
-      .expect("Unable to convert cell_type string to enum variant");
+    let mut cell_data = cell_data_from_value(cell_type, db_cell.data).unwrap_or_else(|err| {
+      report_decode_failure("Cell", db_cell.id, err);
 
-    Cell {
+      GQLCells::Empty(EmptyCell {
+        cell_type: cell_type.to_string(),
+      })
+    });
+
+    // Formulas are evaluated relative to their own cell.
+    if let GQLCells::Formula(formula_cell) = &mut cell_data {
+      formula_cell.cell_id = db_cell.id;
+      formula_cell.portal_id = db_cell.portal_id;
+    }
+
+    Ok(Cell {
       id: db_cell.id,
       portal_id: db_cell.portal_id,
       cell_type,
-      dimensions: db_cell.dimensions,
+      dimension_ids: db_cell.dimensions,
       cell_data,
       created_at: db_cell.created_at,
       created_by: db_cell.created_by,
       updated_at: db_cell.updated_at,
       updated_by: db_cell.updated_by,
-    }
+    })
+  }
+}
+
+// Reads the jsonb data of a cell into the union member of its cell type.
+pub fn cell_data_from_value(
+  cell_type: CellTypes,
+  data: serde_json::Value,
+) -> serde_json::Result<GQLCells> {
+  match cell_type {
+    CellTypes::BasicText => serde_json::from_value(data).map(GQLCells::BasicText),
+    CellTypes::Number => serde_json::from_value(data).map(GQLCells::Number),
+    CellTypes::Date => serde_json::from_value(data).map(GQLCells::Date),
+    CellTypes::Boolean => serde_json::from_value(data).map(GQLCells::Boolean),
+    CellTypes::SingleSelect => serde_json::from_value(data).map(GQLCells::SingleSelect),
+    CellTypes::MultiSelect => serde_json::from_value(data).map(GQLCells::MultiSelect),
+    CellTypes::RichText => serde_json::from_value(data).map(GQLCells::RichText),
+    CellTypes::Formula => serde_json::from_value(data).map(GQLCells::Formula),
   }
 }
 
-#[derive(GraphQLObject, Debug, Serialize, Deserialize)]
+// NOTE: Field names differ between the cell types on purpose, GraphQL doesn't allow the same
+//       field name with different types in fragments on the one union.
+
+#[derive(GraphQLObject, Debug, Clone, Serialize, Deserialize)]
 pub struct BasicTextCell {
   text: String,
 }
 
-#[derive(GraphQLObject, Debug, Serialize, Deserialize)]
+#[derive(GraphQLObject, Debug, Clone, Serialize, Deserialize)]
+pub struct NumberCell {
+  pub number: f64,
+
+  #[graphql(description = "Number of decimal places to display")]
+  pub precision: Option<i32>,
+
+  pub unit: Option<String>,
+}
+
+#[derive(GraphQLObject, Debug, Clone, Serialize, Deserialize)]
+pub struct DateCell {
+  pub date: NaiveDate,
+}
+
+#[derive(GraphQLObject, Debug, Clone, Serialize, Deserialize)]
+pub struct BooleanCell {
+  pub checked: bool,
+}
+
+// The options to choose from are in the meta of the cell's dimensions.
+#[derive(GraphQLObject, Debug, Clone, Serialize, Deserialize)]
+pub struct SingleSelectCell {
+  pub choice: Option<String>,
+}
+
+#[derive(GraphQLObject, Debug, Clone, Serialize, Deserialize)]
+pub struct MultiSelectCell {
+  pub choices: Vec<String>,
+}
+
+#[derive(GraphQLObject, Debug, Clone, Serialize, Deserialize)]
+pub struct RichTextCell {
+  pub markdown: String,
+}
+
+// See services::formula_service for what a formula can contain.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FormulaCell {
+  pub formula: String,
+
+  #[serde(skip)]
+  pub cell_id: Uuid,
+
+  #[serde(skip)]
+  pub portal_id: Uuid,
+}
+
+#[graphql_object(context = GQLContext)]
+impl FormulaCell {
+  fn formula(&self) -> String {
+    self
+      .formula
+      .clone()
+  }
+
+  #[graphql(description = "Evaluated from the current values of the cells the formula references")]
+  async fn evaluation(&self, ctx: &GQLContext) -> FieldResult<FormulaEvaluation> {
+    let evaluation = evaluate_formula(
+      &ctx.formula_graphs,
+      &ctx.db,
+      self.cell_id,
+      self.portal_id,
+      &self.formula,
+    )
+    .await
+    .map_err(field_error)?;
+
+    Ok(FormulaEvaluation {
+      value: evaluation
+        .value
+        .clone()
+        .ok(),
+      error: evaluation
+        .value
+        .err()
+        .map(|err| err.to_string()),
+      dependency_ids: evaluation.dependency_ids,
+    })
+  }
+}
+
+#[derive(GraphQLObject, Debug, Clone)]
+pub struct FormulaEvaluation {
+  #[graphql(description = "Null when the formula has an error")]
+  pub value: Option<f64>,
+
+  pub error: Option<String>,
+
+  #[graphql(description = "Cells the formula reads directly, including those of its rows/columns")]
+  pub dependency_ids: Vec<Uuid>,
+}
+
+#[derive(GraphQLObject, Debug, Clone, Serialize, Deserialize)]
 pub struct EmptyCell {
   cell_type: String,
 }
 
+#[derive(GraphQLInputObject, Debug, Serialize, Deserialize)]
+pub struct NewCell {
+  pub portal_id: Uuid,
+
+  pub cell_type: CellTypes,
+
+  pub dimensions: Vec<Uuid>,
+
+  #[graphql(description = "JSON encoded cell data, must match the shape of the cell_type")]
+  pub cell_data: String,
+}
+
+#[derive(GraphQLInputObject, Debug, Serialize, Deserialize)]
+pub struct UpdateCell {
+  pub id: Uuid,
+
+  pub cell_type: Option<CellTypes>,
+
+  pub dimensions: Option<Vec<Uuid>>,
+
+  #[graphql(description = "JSON encoded cell data, must match the shape of the cell_type")]
+  pub cell_data: Option<String>,
+}
+
 impl Query {
   pub async fn cell_impl(ctx: &GQLContext, cell_id: Uuid) -> FieldResult<Cell> {
     ctx
       .db
-      .get_cell(cell_id)
+      .get_auth0_user_cell(&ctx.auth0_user_id, cell_id)
+      .await
+      .and_then(Cell::try_from)
+      .map_err(field_error)
+  }
+
+  // Cells within a portal that only reference the given dimensions.
+  pub async fn cells_impl(
+    ctx: &GQLContext,
+    portal_id: Uuid,
+    dimension_ids: Vec<Uuid>,
+  ) -> FieldResult<Vec<Cell>> {
+    ctx
+      .db
+      .get_auth0_user_portal(&ctx.auth0_user_id, portal_id)
+      .await
+      .map_err(field_error)?;
+
+    ctx
+      .db
+      .get_cells_by_dimensions(portal_id, &dimension_ids)
+      .await
+      .and_then(try_from_rows)
+      .map_err(field_error)
+  }
+}
+
+impl Mutation {
+  pub async fn create_cells_impl(
+    ctx: &GQLContext,
+    new_cells: Vec<NewCell>,
+  ) -> FieldResult<Vec<Cell>> {
+    let mut db_new_cells = Vec::with_capacity(new_cells.len());
+
+    for new_cell in new_cells {
+      db_new_cells.push(DBNewCell {
+        portal_id: new_cell.portal_id,
+        cell_type: new_cell
+          .cell_type
+          .to_string(),
+        dimensions: new_cell.dimensions,
+        data: serde_json::from_str(&new_cell.cell_data).map_err(field_error)?,
+      });
+    }
+
+    ctx
+      .db
+      .create_cells(&ctx.auth0_user_id, db_new_cells)
+      .await
+      .and_then(try_from_rows)
+      .map_err(field_error)
+  }
+
+  pub async fn update_cell_impl(ctx: &GQLContext, update_cell: UpdateCell) -> FieldResult<Cell> {
+    let data = match update_cell.cell_data {
+      Some(cell_data) => Some(
+        serde_json::from_str::<serde_json::Value>(&cell_data).map_err(field_error)?,
+      ),
+      None => None,
+    };
+
+    let db_update_cell = DBUpdateCell {
+      id: update_cell.id,
+      cell_type: update_cell
+        .cell_type
+        .map(|ct| ct.to_string()),
+      dimensions: update_cell.dimensions,
+      data,
+    };
+
+    ctx
+      .db
+      .update_cell(&ctx.auth0_user_id, db_update_cell)
       .await
-      .map(|db_cell| db_cell.into())
-      .map_err(FieldError::from)
+      .and_then(Cell::try_from)
+      .map_err(field_error)
   }
 }
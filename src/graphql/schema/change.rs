@@ -0,0 +1,300 @@
+use std::convert::TryFrom;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use futures::{Stream, StreamExt};
+use juniper::{graphql_object, FieldError, FieldResult, GraphQLEnum};
+use strum_macros::{EnumString, ToString};
+use uuid::Uuid;
+
+use super::block::Block;
+use super::cell::Cell;
+use super::dimension::Dimension;
+use super::portalview::PortalView;
+use super::Subscription;
+
+use crate::errors::field_error;
+use crate::graphql::authorization::{check_permission, load_role_perms, Permission};
+use crate::graphql::context::GQLContext;
+use crate::services::change_broker::{ChangeEvent, Topic};
+
+pub type ChangeStream<T> = Pin<Box<dyn Stream<Item = FieldResult<T>> + Send>>;
+
+#[derive(
+  Debug, Clone, Copy, PartialEq, Serialize, Deserialize, GraphQLEnum, EnumString, ToString,
+)]
+pub enum ChangeOperation {
+  Created,
+  Updated,
+  Deleted,
+}
+
+pub struct CellChange {
+  pub operation: ChangeOperation,
+  pub cell_id: Uuid,
+}
+
+#[graphql_object(context = GQLContext)]
+impl CellChange {
+  fn operation(&self) -> ChangeOperation {
+    self.operation
+  }
+
+  fn cell_id(&self) -> Uuid {
+    self.cell_id
+  }
+
+  #[graphql(description = "The cell as it is now, null when it has been deleted")]
+  async fn cell(&self, ctx: &GQLContext) -> FieldResult<Option<Cell>> {
+    if self.operation == ChangeOperation::Deleted {
+      return Ok(None);
+    }
+
+    ctx
+      .db
+      .get_auth0_user_cell(&ctx.auth0_user_id, self.cell_id)
+      .await
+      .and_then(Cell::try_from)
+      .map(Some)
+      .map_err(field_error)
+  }
+}
+
+impl From<ChangeEvent> for CellChange {
+  fn from(event: ChangeEvent) -> Self {
+    CellChange {
+      operation: event.operation,
+      cell_id: event.id,
+    }
+  }
+}
+
+pub struct BlockChange {
+  pub operation: ChangeOperation,
+  pub block_id: Uuid,
+}
+
+#[graphql_object(context = GQLContext)]
+impl BlockChange {
+  fn operation(&self) -> ChangeOperation {
+    self.operation
+  }
+
+  fn block_id(&self) -> Uuid {
+    self.block_id
+  }
+
+  #[graphql(description = "The block as it is now, null when it has been deleted")]
+  async fn block(&self, ctx: &GQLContext) -> FieldResult<Option<Block>> {
+    if self.operation == ChangeOperation::Deleted {
+      return Ok(None);
+    }
+
+    ctx
+      .db
+      .get_auth0_user_block(&ctx.auth0_user_id, self.block_id)
+      .await
+      .and_then(Block::try_from)
+      .map(Some)
+      .map_err(field_error)
+  }
+}
+
+impl From<ChangeEvent> for BlockChange {
+  fn from(event: ChangeEvent) -> Self {
+    BlockChange {
+      operation: event.operation,
+      block_id: event.id,
+    }
+  }
+}
+
+pub struct DimensionChange {
+  pub operation: ChangeOperation,
+  pub dimension_id: Uuid,
+}
+
+#[graphql_object(context = GQLContext)]
+impl DimensionChange {
+  fn operation(&self) -> ChangeOperation {
+    self.operation
+  }
+
+  fn dimension_id(&self) -> Uuid {
+    self.dimension_id
+  }
+
+  #[graphql(description = "The dimension as it is now, null when it has been deleted")]
+  async fn dimension(&self, ctx: &GQLContext) -> FieldResult<Option<Dimension>> {
+    if self.operation == ChangeOperation::Deleted {
+      return Ok(None);
+    }
+
+    ctx
+      .db
+      .get_auth0_user_dimension(&ctx.auth0_user_id, self.dimension_id)
+      .await
+      .and_then(Dimension::try_from)
+      .map(Some)
+      .map_err(field_error)
+  }
+}
+
+impl From<ChangeEvent> for DimensionChange {
+  fn from(event: ChangeEvent) -> Self {
+    DimensionChange {
+      operation: event.operation,
+      dimension_id: event.id,
+    }
+  }
+}
+
+pub struct PortalViewChange {
+  pub operation: ChangeOperation,
+  pub portal_view_id: Uuid,
+}
+
+#[graphql_object(context = GQLContext)]
+impl PortalViewChange {
+  fn operation(&self) -> ChangeOperation {
+    self.operation
+  }
+
+  fn portal_view_id(&self) -> Uuid {
+    self.portal_view_id
+  }
+
+  #[graphql(description = "The portal view as it is now, null when it has been deleted")]
+  async fn portal_view(&self, ctx: &GQLContext) -> FieldResult<Option<PortalView>> {
+    if self.operation == ChangeOperation::Deleted {
+      return Ok(None);
+    }
+
+    ctx
+      .db
+      .get_auth0_user_portal_view(&ctx.auth0_user_id, self.portal_view_id)
+      .await
+      .and_then(PortalView::try_from)
+      .map(Some)
+      .map_err(field_error)
+  }
+}
+
+impl From<ChangeEvent> for PortalViewChange {
+  fn from(event: ChangeEvent) -> Self {
+    PortalViewChange {
+      operation: event.operation,
+      portal_view_id: event.id,
+    }
+  }
+}
+
+// The context lives as long as the connection, so everything cached in it is only good for one
+// change. Roles and portal members can change while the connection is open, so the user's access
+// to the portal is checked again for every change instead of only when subscribing.
+fn change_stream<T>(ctx: &GQLContext, topic: Topic, portal_id: Uuid) -> ChangeStream<T>
+where
+  T: From<ChangeEvent> + Send + 'static,
+{
+  let db = ctx.db.clone();
+  let auth0_user_id = ctx
+    .auth0_user_id
+    .clone();
+  let role_perms = Arc::clone(&ctx.role_perms);
+  let formula_graphs = Arc::clone(&ctx.formula_graphs);
+
+  Box::pin(
+    ctx
+      .changes
+      .subscribe(topic)
+      .then(move |event| {
+        let db = db.clone();
+        let auth0_user_id = auth0_user_id.clone();
+        let role_perms = Arc::clone(&role_perms);
+        let formula_graphs = Arc::clone(&formula_graphs);
+
+        async move {
+          formula_graphs
+            .lock()
+            .await
+            .clear();
+
+          let perms = load_role_perms(&db, &auth0_user_id).await?;
+          check_permission(&perms, Permission::ViewPortal)?;
+
+          *role_perms
+            .lock()
+            .await = Some(perms);
+
+          db.get_auth0_user_portal(&auth0_user_id, portal_id)
+            .await
+            .map_err(field_error)?;
+
+          Ok::<T, FieldError>(T::from(event))
+        }
+      }),
+  )
+}
+
+impl Subscription {
+  pub async fn cell_changed_impl(
+    ctx: &GQLContext,
+    portal_id: Uuid,
+  ) -> FieldResult<ChangeStream<CellChange>> {
+    // Make sure the user can actually see the portal before handing out its changes.
+    ctx
+      .db
+      .get_auth0_user_portal(&ctx.auth0_user_id, portal_id)
+      .await
+      .map_err(field_error)?;
+
+    Ok(change_stream(ctx, Topic::PortalCells(portal_id), portal_id))
+  }
+
+  pub async fn block_changed_impl(
+    ctx: &GQLContext,
+    portal_view_id: Uuid,
+  ) -> FieldResult<ChangeStream<BlockChange>> {
+    let portal_view = ctx
+      .db
+      .get_auth0_user_portal_view(&ctx.auth0_user_id, portal_view_id)
+      .await
+      .map_err(field_error)?;
+
+    Ok(change_stream(
+      ctx,
+      Topic::PortalViewBlocks(portal_view_id),
+      portal_view.portal_id,
+    ))
+  }
+
+  pub async fn dimension_changed_impl(
+    ctx: &GQLContext,
+    portal_id: Uuid,
+  ) -> FieldResult<ChangeStream<DimensionChange>> {
+    ctx
+      .db
+      .get_auth0_user_portal(&ctx.auth0_user_id, portal_id)
+      .await
+      .map_err(field_error)?;
+
+    Ok(change_stream(
+      ctx,
+      Topic::PortalDimensions(portal_id),
+      portal_id,
+    ))
+  }
+
+  pub async fn portal_view_changed_impl(
+    ctx: &GQLContext,
+    portal_id: Uuid,
+  ) -> FieldResult<ChangeStream<PortalViewChange>> {
+    ctx
+      .db
+      .get_auth0_user_portal(&ctx.auth0_user_id, portal_id)
+      .await
+      .map_err(field_error)?;
+
+    Ok(change_stream(ctx, Topic::PortalViews(portal_id), portal_id))
+  }
+}
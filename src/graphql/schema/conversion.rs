@@ -0,0 +1,43 @@
+use std::convert::TryFrom;
+use std::fmt::Display;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use uuid::Uuid;
+
+use crate::errors::ApiError;
+
+// Conversions from db rows into GraphQL types never panic. Jsonb that doesn't decode falls back
+// to the Empty variant of its union, and columns that can't be read at all fail with an internal
+// error. Either way the row is reported here so the bad data can be found and fixed.
+pub fn report_decode_failure(entity: &str, id: Uuid, err: impl Display) {
+  println!("Unable to decode {} {}: {}", entity, id, err);
+}
+
+// Parses a strum enum column, ie. block_type or egress.
+pub fn parse_column<T: FromStr>(entity: &str, id: Uuid, column: &str, value: &str) -> Result<T> {
+  T::from_str(value).map_err(|_| {
+    let err = anyhow!("unknown {} \"{}\"", column, value);
+
+    report_decode_failure(entity, id, &err);
+
+    ApiError::Internal(Arc::new(err)).raise()
+  })
+}
+
+// Converts a list of rows, failing if any of them can't be converted. Every row is converted
+// before failing, so that all of the bad ones get reported, not just the first.
+pub fn try_from_rows<D, T>(rows: Vec<D>) -> Result<Vec<T>>
+where
+  T: TryFrom<D, Error = anyhow::Error>,
+{
+  let results: Vec<Result<T>> = rows
+    .into_iter()
+    .map(T::try_from)
+    .collect();
+
+  results
+    .into_iter()
+    .collect()
+}
@@ -1,10 +1,17 @@
 use chrono::{DateTime, Utc};
-use juniper::{FieldError, FieldResult, GraphQLEnum, GraphQLObject, GraphQLUnion};
-use std::str::FromStr;
-use strum_macros::EnumString;
+use juniper::{
+  FieldResult, GraphQLEnum, GraphQLInputObject, GraphQLObject, GraphQLUnion,
+};
+use std::convert::{TryFrom, TryInto};
+use strum_macros::{EnumString, ToString};
+use crate::errors::field_error;
 use crate::{graphql::context::GQLContext, services::db::dimension_service::DBDimension};
+use crate::services::db::dimension_service::{DBNewDimension, DBUpdateDimension};
 use uuid::Uuid;
 
+use super::conversion::{parse_column, try_from_rows};
+use super::pagination::{page_args, OrderBy};
+use super::Mutation;
 use super::Query;
 
 
@@ -15,13 +22,13 @@ use super::Query;
 //   Empty
 // }
 
-#[derive(Debug, Serialize, Deserialize, GraphQLEnum, EnumString)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, GraphQLEnum, EnumString, ToString)]
 pub enum DimensionTypes {
   BasicTableRow,
   BasicTableColumn,
 }
 
-#[derive(GraphQLObject, Debug, Serialize, Deserialize)]
+#[derive(GraphQLObject, Debug, Clone, Serialize, Deserialize)]
 pub struct Dimension {
   pub id: Uuid,
 
@@ -33,7 +40,8 @@ pub struct Dimension {
   #[serde(rename = "dimensionType")]
   pub dimension_type: DimensionTypes,
 
-  // pub meta: serde_json::Value,
+  #[graphql(description = "JSON encoded dimension meta")]
+  pub meta: String,
 
   #[serde(rename = "createdAt")]
   pub created_at: DateTime<Utc>,
@@ -48,31 +56,178 @@ pub struct Dimension {
   pub updated_by: Uuid,
 }
 
-impl From<DBDimension> for Dimension {
-  fn from(db_dimension: DBDimension) -> Self {
-    let dimension_type = DimensionTypes::from_str(db_dimension.dimension_type.as_str())
-      .expect("Unable to convert dimension_type to enum variat");
+impl TryFrom<DBDimension> for Dimension {
+  type Error = anyhow::Error;
 
-    Dimension {
+  fn try_from(db_dimension: DBDimension) -> Result<Self, Self::Error> {
+    let dimension_type = parse_column(
+      "Dimension",
+      db_dimension.id,
+      "dimension_type",
+      &db_dimension.dimension_type,
+    )?;
+
+    Ok(Dimension {
       id: db_dimension.id,
       portal_id: db_dimension.portal_id,
       name: db_dimension.name,
       dimension_type,
+      meta: db_dimension
+        .meta
+        .to_string(),
       created_at: db_dimension.created_at,
       created_by: db_dimension.created_by,
       updated_at: db_dimension.updated_at,
       updated_by: db_dimension.updated_by,
-    }
+    })
+  }
+}
+
+connection!(DimensionConnection, DimensionEdge, Dimension);
+
+#[derive(GraphQLInputObject, Debug, Serialize, Deserialize)]
+pub struct NewDimension {
+  pub portal_id: Uuid,
+
+  pub name: String,
+
+  pub dimension_type: DimensionTypes,
+
+  #[graphql(
+    description = "JSON encoded dimension meta, defaults to {}. Options of select cells go in \"options\""
+  )]
+  pub meta: Option<String>,
+}
+
+#[derive(GraphQLInputObject, Debug, Serialize, Deserialize)]
+pub struct UpdateDimension {
+  pub id: Uuid,
+
+  pub name: Option<String>,
+
+  #[graphql(description = "JSON encoded dimension meta")]
+  pub meta: Option<String>,
+}
+
+#[derive(GraphQLInputObject, Debug, Default, Serialize, Deserialize)]
+pub struct DimensionFilter {
+  pub dimension_type: Option<DimensionTypes>,
+
+  #[graphql(description = "Case insensitive")]
+  pub name_contains: Option<String>,
+}
+
+fn parse_meta(meta: Option<String>) -> FieldResult<Option<serde_json::Value>> {
+  match meta {
+    Some(meta) => Ok(Some(serde_json::from_str(&meta).map_err(field_error)?)),
+    None => Ok(None),
+  }
+}
+
+impl NewDimension {
+  fn into_db(self) -> FieldResult<DBNewDimension> {
+    Ok(DBNewDimension {
+      portal_id: self.portal_id,
+      name: self.name,
+      dimension_type: self
+        .dimension_type
+        .to_string(),
+      meta: parse_meta(self.meta)?.unwrap_or_else(|| serde_json::json!({})),
+    })
   }
 }
 
 impl Query {
-  pub async fn dimensions_impl(ctx: &GQLContext, portal_id: Uuid) -> FieldResult<Vec<Dimension>> {
+  pub async fn dimensions_impl(
+    ctx: &GQLContext,
+    portal_id: Uuid,
+    filter: Option<DimensionFilter>,
+    order_by: Option<OrderBy>,
+    first: Option<i32>,
+    after: Option<String>,
+  ) -> FieldResult<DimensionConnection> {
+    let (first, after) = page_args(first, after)?;
+
     ctx
-    .db
-    .get_dimensions(portal_id)
-    .await
-    .map(|dims| dims.into_iter().map(|d| d.into()).collect())
-    .map_err(FieldError::from)
+      .db
+      .get_auth0_user_portal(&ctx.auth0_user_id, portal_id)
+      .await
+      .map_err(field_error)?;
+
+    ctx
+      .db
+      .get_dimensions(
+        portal_id,
+        filter
+          .unwrap_or_default()
+          .into(),
+        order_by.unwrap_or_default(),
+        first,
+        after,
+      )
+      .await
+      .and_then(|page| page.try_into())
+      .map_err(field_error)
   }
-}
\ No newline at end of file
+}
+
+impl Mutation {
+  pub async fn create_dimension_impl(
+    ctx: &GQLContext,
+    new_dimension: NewDimension,
+  ) -> FieldResult<Dimension> {
+    ctx
+      .db
+      .create_dimension(&ctx.auth0_user_id, new_dimension.into_db()?)
+      .await
+      .and_then(Dimension::try_from)
+      .map_err(field_error)
+  }
+
+  pub async fn create_dimensions_impl(
+    ctx: &GQLContext,
+    new_dimensions: Vec<NewDimension>,
+  ) -> FieldResult<Vec<Dimension>> {
+    let db_new_dimensions = new_dimensions
+      .into_iter()
+      .map(|d| d.into_db())
+      .collect::<FieldResult<Vec<DBNewDimension>>>()?;
+
+    ctx
+      .db
+      .create_dimensions(&ctx.auth0_user_id, db_new_dimensions)
+      .await
+      .and_then(try_from_rows)
+      .map_err(field_error)
+  }
+
+  pub async fn update_dimension_impl(
+    ctx: &GQLContext,
+    update_dimension: UpdateDimension,
+  ) -> FieldResult<Dimension> {
+    let db_update_dimension = DBUpdateDimension {
+      id: update_dimension.id,
+      name: update_dimension.name,
+      meta: parse_meta(update_dimension.meta)?,
+    };
+
+    ctx
+      .db
+      .update_dimension(&ctx.auth0_user_id, db_update_dimension)
+      .await
+      .and_then(Dimension::try_from)
+      .map_err(field_error)
+  }
+
+  pub async fn delete_dimension_impl(
+    ctx: &GQLContext,
+    dimension_id: Uuid,
+  ) -> FieldResult<Dimension> {
+    ctx
+      .db
+      .delete_dimension(&ctx.auth0_user_id, dimension_id)
+      .await
+      .and_then(Dimension::try_from)
+      .map_err(field_error)
+  }
+}
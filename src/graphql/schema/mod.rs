@@ -1,7 +1,10 @@
-use juniper::{graphql_object, DefaultScalarValue, EmptySubscription, FieldResult, RootNode};
+use juniper::{graphql_object, graphql_subscription, DefaultScalarValue, FieldResult, RootNode};
 use uuid::Uuid;
 
 // pub mod misc;
+#[macro_use]
+pub mod pagination;
+pub mod conversion;
 pub mod org;
 pub mod user;
 pub mod role;
@@ -10,19 +13,22 @@ pub mod portalview;
 pub mod dimension;
 pub mod block;
 pub mod cell;
+pub mod change;
 
+use super::authorization::Permission;
 use super::context::GQLContext;
-use org::{NewOrg, Org};
+use org::{NewOrg, Org, OrgConnection};
 use user::{NewUser, User, UpdateUser};
 use role::{NewRole, Role};
-use portal::{Portal};
-use portalview::{PortalView};
-use dimension::{Dimension};
-use block::{Block};
-use cell::{Cell};
+use pagination::OrderBy;
+use portal::{NewPortal, Portal, PortalConnection, PortalFilter, PortalInvitation, UpdatePortal};
+use portalview::{EgressTypes, NewPortalView, PortalView, PortalViewConnection, UpdatePortalView};
+use dimension::{Dimension, DimensionConnection, DimensionFilter, NewDimension, UpdateDimension};
+use block::{Block, BlockMove, NewBlock, UpdateBlock};
+use cell::{Cell, NewCell, UpdateCell};
+use change::{BlockChange, CellChange, ChangeStream, DimensionChange, PortalViewChange};
 
-pub type Schema =
-  RootNode<'static, Query, Mutation, EmptySubscription<GQLContext>, DefaultScalarValue>;
+pub type Schema = RootNode<'static, Query, Mutation, Subscription, DefaultScalarValue>;
 
 pub struct Query;
 
@@ -36,21 +42,29 @@ impl Query {
 
   // Org
   async fn org(ctx: &GQLContext, org_id: Uuid) -> FieldResult<Org> {
+    ctx.authorize(Permission::ViewOrg).await?;
     Query::org_impl(ctx, org_id).await
   }
 
   // Orgs, scoped to a User
   #[graphql(description = "Orgs are scoped to the auth0 id of the requesting user")]
-  async fn orgs(ctx: &GQLContext) -> FieldResult<Vec<Org>> {
-    Query::orgs_impl(ctx).await
+  async fn orgs(
+    ctx: &GQLContext,
+    first: Option<i32>,
+    after: Option<String>,
+  ) -> FieldResult<OrgConnection> {
+    ctx.authorize(Permission::ViewOrg).await?;
+    Query::orgs_impl(ctx, first, after).await
   }
 
   // User
 
   async fn user(ctx: &GQLContext, user_id: Uuid) -> FieldResult<User> {
+    ctx.authorize(Permission::ViewOrg).await?;
     Query::user_impl(ctx, user_id).await
   }
 
+  // Not authorized, since this is what creates the user the first time they log in.
   async fn current_user(ctx: &GQLContext) -> FieldResult<User> {
     Query::current_user_impl(ctx).await
   }
@@ -58,42 +72,87 @@ impl Query {
   // Role
 
   async fn role(ctx: &GQLContext, role_id: Uuid) -> FieldResult<Role> {
+    ctx.authorize(Permission::ViewOrg).await?;
     Query::role_impl(ctx, role_id).await
   }
 
   // Portal
 
   async fn portal(ctx: &GQLContext, portal_id: Uuid) -> FieldResult<Portal> {
+    ctx.authorize(Permission::ViewPortal).await?;
     Query::portal_impl(ctx, portal_id).await
   }
 
-  async fn portals(ctx: &GQLContext) -> FieldResult<Vec<Portal>> {
-    Query::user_portals_impl(ctx).await
+  async fn portals(
+    ctx: &GQLContext,
+    filter: Option<PortalFilter>,
+    order_by: Option<OrderBy>,
+    first: Option<i32>,
+    after: Option<String>,
+  ) -> FieldResult<PortalConnection> {
+    ctx.authorize(Permission::ViewPortal).await?;
+    Query::user_portals_impl(ctx, filter, order_by, first, after).await
   }
 
   // Portal View
 
-  async fn portalviews(ctx: &GQLContext, portal_id: Uuid) -> FieldResult<Vec<PortalView>> {
-    Query::portalviews_impl(ctx, portal_id).await
+  async fn portal_view(ctx: &GQLContext, portal_view_id: Uuid) -> FieldResult<PortalView> {
+    ctx.authorize(Permission::ViewPortal).await?;
+    Query::portalview_impl(ctx, portal_view_id).await
+  }
+
+  async fn portalviews(
+    ctx: &GQLContext,
+    portal_id: Uuid,
+    first: Option<i32>,
+    after: Option<String>,
+  ) -> FieldResult<PortalViewConnection> {
+    ctx.authorize(Permission::ViewPortal).await?;
+    Query::portalviews_impl(ctx, portal_id, first, after).await
   }
 
   // Dimension
 
-  async fn dimensions(ctx: &GQLContext, portal_id: Uuid) -> FieldResult<Vec<Dimension>> {
-    Query::dimensions_impl(ctx, portal_id).await
+  async fn dimensions(
+    ctx: &GQLContext,
+    portal_id: Uuid,
+    filter: Option<DimensionFilter>,
+    order_by: Option<OrderBy>,
+    first: Option<i32>,
+    after: Option<String>,
+  ) -> FieldResult<DimensionConnection> {
+    ctx.authorize(Permission::ViewPortal).await?;
+    Query::dimensions_impl(ctx, portal_id, filter, order_by, first, after).await
   }
 
   // Block
 
   async fn block(ctx: &GQLContext, block_id: Uuid) -> FieldResult<Block> {
+    ctx.authorize(Permission::ViewPortal).await?;
     Query::block_impl(ctx, block_id).await
   }
 
+  async fn blocks(ctx: &GQLContext, portal_view_id: Uuid) -> FieldResult<Vec<Block>> {
+    ctx.authorize(Permission::ViewPortal).await?;
+    Query::blocks_impl(ctx, portal_view_id).await
+  }
+
   // Cell
 
   async fn cell(ctx: &GQLContext, cell_id: Uuid) -> FieldResult<Cell> {
+    ctx.authorize(Permission::ViewPortal).await?;
     Query::cell_impl(ctx, cell_id).await
   }
+
+  #[graphql(description = "Cells in the portal that only reference the given dimensions")]
+  async fn cells(
+    ctx: &GQLContext,
+    portal_id: Uuid,
+    dimension_ids: Vec<Uuid>,
+  ) -> FieldResult<Vec<Cell>> {
+    ctx.authorize(Permission::ViewPortal).await?;
+    Query::cells_impl(ctx, portal_id, dimension_ids).await
+  }
 }
 
 pub struct Mutation;
@@ -102,26 +161,197 @@ pub struct Mutation;
 impl Mutation {
   // Org
   async fn create_org(ctx: &GQLContext, new_org: NewOrg) -> FieldResult<Org> {
+    ctx.authorize(Permission::CreateOrg).await?;
     Mutation::create_org_impl(ctx, new_org).await
   }
 
   // User
 
   async fn create_user(ctx: &GQLContext, new_user: NewUser) -> FieldResult<User> {
+    ctx.authorize(Permission::AddOrgUser).await?;
     Mutation::create_user_impl(ctx, new_user).await
   }
 
   async fn update_user(ctx: &GQLContext, update_user: UpdateUser) -> FieldResult<User> {
+    ctx.authorize(Permission::EditOrgUsers).await?;
     Mutation::update_user_impl(ctx, update_user).await
   }
 
+  // Not authorized, the caller may not have a user, or any roles, until the invitation is accepted.
+  async fn accept_invitation(ctx: &GQLContext, token: String) -> FieldResult<User> {
+    Mutation::accept_invitation_impl(ctx, token).await
+  }
+
   // Role
 
   async fn create_role(ctx: &GQLContext, new_role: NewRole) -> FieldResult<Role> {
+    ctx.authorize(Permission::EditOrgUsers).await?;
     Mutation::create_role(ctx, new_role).await
   }
+
+  // Portal
+
+  async fn create_portal(ctx: &GQLContext, new_portal: NewPortal) -> FieldResult<Portal> {
+    ctx.authorize(Permission::CreatePortal).await?;
+    Mutation::create_portal_impl(ctx, new_portal).await
+  }
+
+  async fn update_portal(ctx: &GQLContext, update_portal: UpdatePortal) -> FieldResult<Portal> {
+    ctx.authorize(Permission::EditPortal).await?;
+    Mutation::update_portal_impl(ctx, update_portal).await
+  }
+
+  async fn delete_portal(ctx: &GQLContext, portal_id: Uuid) -> FieldResult<Portal> {
+    ctx.authorize(Permission::EditPortal).await?;
+    Mutation::delete_portal_impl(ctx, portal_id).await
+  }
+
+  async fn invite_user_to_portal(
+    ctx: &GQLContext,
+    email: String,
+    portal_id: Uuid,
+    egress: EgressTypes,
+  ) -> FieldResult<PortalInvitation> {
+    ctx.authorize(Permission::EditPortal).await?;
+    Mutation::invite_user_to_portal_impl(ctx, email, portal_id, egress).await
+  }
+
+  // Portal View
+
+  async fn create_portal_view(
+    ctx: &GQLContext,
+    new_portal_view: NewPortalView,
+  ) -> FieldResult<PortalView> {
+    ctx.authorize(Permission::EditPortal).await?;
+    Mutation::create_portalview_impl(ctx, new_portal_view).await
+  }
+
+  async fn update_portal_view(
+    ctx: &GQLContext,
+    update_portal_view: UpdatePortalView,
+  ) -> FieldResult<PortalView> {
+    ctx.authorize(Permission::EditPortal).await?;
+    Mutation::update_portalview_impl(ctx, update_portal_view).await
+  }
+
+  async fn delete_portal_view(ctx: &GQLContext, portal_view_id: Uuid) -> FieldResult<PortalView> {
+    ctx.authorize(Permission::EditPortal).await?;
+    Mutation::delete_portalview_impl(ctx, portal_view_id).await
+  }
+
+  // Block
+
+  async fn create_block(ctx: &GQLContext, new_block: NewBlock) -> FieldResult<Block> {
+    ctx.authorize(Permission::EditPortal).await?;
+    Mutation::create_block_impl(ctx, new_block).await
+  }
+
+  async fn update_block(ctx: &GQLContext, update_block: UpdateBlock) -> FieldResult<Block> {
+    ctx.authorize(Permission::EditPortal).await?;
+    Mutation::update_block_impl(ctx, update_block).await
+  }
+
+  #[graphql(description = "Moves blocks of a portal view at once, returns every block that moved")]
+  async fn move_blocks(
+    ctx: &GQLContext,
+    portal_view_id: Uuid,
+    moves: Vec<BlockMove>,
+  ) -> FieldResult<Vec<Block>> {
+    ctx.authorize(Permission::EditPortal).await?;
+    Mutation::move_blocks_impl(ctx, portal_view_id, moves).await
+  }
+
+  async fn delete_block(ctx: &GQLContext, block_id: Uuid) -> FieldResult<Block> {
+    ctx.authorize(Permission::EditPortal).await?;
+    Mutation::delete_block_impl(ctx, block_id).await
+  }
+
+  // Dimension
+
+  async fn create_dimension(
+    ctx: &GQLContext,
+    new_dimension: NewDimension,
+  ) -> FieldResult<Dimension> {
+    ctx.authorize(Permission::EditPortal).await?;
+    Mutation::create_dimension_impl(ctx, new_dimension).await
+  }
+
+  async fn create_dimensions(
+    ctx: &GQLContext,
+    new_dimensions: Vec<NewDimension>,
+  ) -> FieldResult<Vec<Dimension>> {
+    ctx.authorize(Permission::EditPortal).await?;
+    Mutation::create_dimensions_impl(ctx, new_dimensions).await
+  }
+
+  async fn update_dimension(
+    ctx: &GQLContext,
+    update_dimension: UpdateDimension,
+  ) -> FieldResult<Dimension> {
+    ctx.authorize(Permission::EditPortal).await?;
+    Mutation::update_dimension_impl(ctx, update_dimension).await
+  }
+
+  #[graphql(description = "Fails if any cells still reference the dimension")]
+  async fn delete_dimension(ctx: &GQLContext, dimension_id: Uuid) -> FieldResult<Dimension> {
+    ctx.authorize(Permission::EditPortal).await?;
+    Mutation::delete_dimension_impl(ctx, dimension_id).await
+  }
+
+  // Cell
+
+  async fn create_cells(ctx: &GQLContext, new_cells: Vec<NewCell>) -> FieldResult<Vec<Cell>> {
+    ctx.authorize(Permission::EditPortal).await?;
+    Mutation::create_cells_impl(ctx, new_cells).await
+  }
+
+  async fn update_cell(ctx: &GQLContext, update_cell: UpdateCell) -> FieldResult<Cell> {
+    ctx.authorize(Permission::EditPortal).await?;
+    Mutation::update_cell_impl(ctx, update_cell).await
+  }
+}
+
+pub struct Subscription;
+
+#[graphql_subscription(context = GQLContext)]
+impl Subscription {
+  #[graphql(description = "Cells created, updated or deleted in the portal")]
+  async fn cell_changed(
+    ctx: &GQLContext,
+    portal_id: Uuid,
+  ) -> FieldResult<ChangeStream<CellChange>> {
+    ctx.authorize(Permission::ViewPortal).await?;
+    Subscription::cell_changed_impl(ctx, portal_id).await
+  }
+
+  #[graphql(description = "Blocks created, updated or deleted in the portal view")]
+  async fn block_changed(
+    ctx: &GQLContext,
+    portal_view_id: Uuid,
+  ) -> FieldResult<ChangeStream<BlockChange>> {
+    ctx.authorize(Permission::ViewPortal).await?;
+    Subscription::block_changed_impl(ctx, portal_view_id).await
+  }
+
+  #[graphql(description = "Dimensions created, updated or deleted in the portal")]
+  async fn dimension_changed(
+    ctx: &GQLContext,
+    portal_id: Uuid,
+  ) -> FieldResult<ChangeStream<DimensionChange>> {
+    ctx.authorize(Permission::ViewPortal).await?;
+    Subscription::dimension_changed_impl(ctx, portal_id).await
+  }
+
+  #[graphql(description = "Portal views created, updated or deleted in the portal")]
+  async fn portal_view_changed(
+    ctx: &GQLContext,
+    portal_id: Uuid,
+  ) -> FieldResult<ChangeStream<PortalViewChange>> {
+    ctx.authorize(Permission::ViewPortal).await?;
+    Subscription::portal_view_changed_impl(ctx, portal_id).await
+  }
 }
 
 pub fn create_schema() -> Schema {
-  RootNode::new(Query, Mutation, EmptySubscription::new())
+  RootNode::new(Query, Mutation, Subscription)
 }
@@ -1,13 +1,16 @@
 use chrono::{DateTime, Utc};
-use juniper::{FieldError, FieldResult};
+use juniper::FieldResult;
+use std::convert::TryInto;
 
 use juniper::{GraphQLInputObject, GraphQLObject};
 
+use super::pagination::page_args;
 use super::Mutation;
 use super::Query;
 
 use super::user::{UpdateUser, User};
 
+use crate::errors::field_error;
 use crate::graphql::context::GQLContext;
 use crate::services::db::org_service::{DBNewOrg, DBOrg};
 use uuid::Uuid;
@@ -43,44 +46,37 @@ impl From<DBOrg> for Org {
     }
   }
 }
+
+connection!(OrgConnection, OrgEdge, Org);
+
 #[derive(GraphQLInputObject, Debug, Serialize, Deserialize)]
 pub struct NewOrg {
   pub name: String,
 }
 
 impl Query {
-  pub async fn orgs_impl(ctx: &GQLContext) -> FieldResult<Vec<Org>> {
-    let user = ctx
-      .db
-      .get_user_by_auth0_id(&ctx.auth0_user_id)
-      .await?;
-
-    let user_org_ids = user.org_ids.clone();
+  pub async fn orgs_impl(
+    ctx: &GQLContext,
+    first: Option<i32>,
+    after: Option<String>,
+  ) -> FieldResult<OrgConnection> {
+    let (first, after) = page_args(first, after)?;
 
-    let orgs_by_id = ctx
-      .org_loader
-      .load_many(user.org_ids.into())
-      .await;
-
-    let orgs = orgs_by_id
-      .into_iter()
-      .fold(Vec::new(), |mut acc, (id, o)| {
-        if user_org_ids.contains(&id) {
-          acc.push(o)
-        };
-        acc
-      });
-
-    Ok(orgs)
+    ctx
+      .db
+      .get_auth0_user_orgs(&ctx.auth0_user_id, first, after)
+      .await
+      .and_then(|page| page.try_into())
+      .map_err(field_error)
   }
 
   pub async fn org_impl(ctx: &GQLContext, org_id: Uuid) -> FieldResult<Org> {
     ctx
       .db
-      .get_org(org_id)
+      .get_auth0_user_org(&ctx.auth0_user_id, org_id)
       .await
       .map(|org| -> Org { org.into() })
-      .map_err(FieldError::from)
+      .map_err(field_error)
   }
 }
 
@@ -91,14 +87,14 @@ impl Mutation {
       .create_org(&ctx.auth0_user_id, DBNewOrg { name: new_org.name })
       .await
       .map(|org| -> Org { org.into() })
-      .map_err(FieldError::from)?;
+      .map_err(field_error)?;
 
     let user = ctx
       .db
       .get_user_by_auth0_id(&ctx.auth0_user_id)
       .await
       .map(|db_user| -> User { db_user.into() })
-      .map_err(FieldError::from)?;
+      .map_err(field_error)?;
 
     let mut new_org_ids = user.org_ids.clone();
     new_org_ids.push(created_org.id);
@@ -118,7 +114,7 @@ impl Mutation {
       .db
       .update_user(&ctx.auth0_user_id, user_patch.into())
       .await
-      .map_err(FieldError::from)?;
+      .map_err(field_error)?;
 
     Ok(created_org)
   }
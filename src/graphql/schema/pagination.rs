@@ -0,0 +1,275 @@
+use chrono::{DateTime, Utc};
+use juniper::{FieldError, FieldResult, GraphQLEnum, GraphQLObject};
+use uuid::Uuid;
+
+use crate::errors::ApiError;
+use crate::services::db::pagination::DBCursor;
+
+pub const DEFAULT_PAGE_SIZE: i32 = 50;
+
+pub const MAX_PAGE_SIZE: i32 = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq, GraphQLEnum)]
+pub enum OrderBy {
+  CreatedAtAsc,
+  CreatedAtDesc,
+  NameAsc,
+  NameDesc,
+}
+
+impl Default for OrderBy {
+  fn default() -> Self {
+    OrderBy::CreatedAtAsc
+  }
+}
+
+#[derive(GraphQLObject, Debug, Clone)]
+pub struct PageInfo {
+  pub has_next_page: bool,
+
+  #[graphql(description = "Pass as `after` to fetch the next page")]
+  pub end_cursor: Option<String>,
+}
+
+// Cursors are opaque to clients, they are the base64 encoded "<created_at>|<id>|<name>" of the
+// row. The name goes last since it can contain anything, separators included.
+pub fn encode_cursor(created_at: DateTime<Utc>, id: Uuid, name: &str) -> String {
+  base64::encode(format!("{}|{}|{}", created_at.to_rfc3339(), id, name))
+}
+
+pub fn decode_cursor(cursor: &str) -> FieldResult<DBCursor> {
+  let invalid = || -> FieldError {
+    ApiError::Validation(format!("Invalid cursor: {}", cursor)).into()
+  };
+
+  let decoded = base64::decode(cursor)
+    .ok()
+    .and_then(|bytes| String::from_utf8(bytes).ok())
+    .ok_or_else(invalid)?;
+
+  let mut parts = decoded.splitn(3, '|');
+
+  match (parts.next(), parts.next(), parts.next()) {
+    (Some(created_at), Some(id), Some(name)) => Ok(DBCursor {
+      created_at: DateTime::parse_from_rfc3339(created_at)
+        .map_err(|_| invalid())?
+        .with_timezone(&Utc),
+      id: Uuid::parse_str(id).map_err(|_| invalid())?,
+      name: String::from(name),
+    }),
+    _ => Err(invalid()),
+  }
+}
+
+// Turns the `first` and `after` connection arguments into what the db layer takes.
+pub fn page_args(
+  first: Option<i32>,
+  after: Option<String>,
+) -> FieldResult<(i64, Option<DBCursor>)> {
+  let first = first.unwrap_or(DEFAULT_PAGE_SIZE);
+
+  if first < 0 || first > MAX_PAGE_SIZE {
+    return Err(
+      ApiError::Validation(format!(
+        "first must be between 0 and {}",
+        MAX_PAGE_SIZE
+      ))
+      .into(),
+    );
+  }
+
+  let after = match after {
+    Some(cursor) => Some(decode_cursor(&cursor)?),
+    None => None,
+  };
+
+  Ok((first as i64, after))
+}
+
+// Relay style connection and edge types for a node type. Nodes need `id`, `created_at` and `name`
+// fields, since that is what the cursors are built from.
+macro_rules! connection {
+  ($connection:ident, $edge:ident, $node:ty) => {
+    #[derive(juniper::GraphQLObject, Debug)]
+    #[graphql(context = crate::graphql::context::GQLContext)]
+    pub struct $edge {
+      pub cursor: String,
+
+      pub node: $node,
+    }
+
+    #[derive(juniper::GraphQLObject, Debug)]
+    #[graphql(context = crate::graphql::context::GQLContext)]
+    pub struct $connection {
+      pub edges: Vec<$edge>,
+
+      pub page_info: crate::graphql::schema::pagination::PageInfo,
+    }
+
+    impl<T> std::convert::TryFrom<crate::services::db::pagination::DBPage<T>> for $connection
+    where
+      $node: std::convert::TryFrom<T>,
+      <$node as std::convert::TryFrom<T>>::Error: Into<anyhow::Error>,
+    {
+      type Error = anyhow::Error;
+
+      fn try_from(
+        page: crate::services::db::pagination::DBPage<T>,
+      ) -> Result<Self, Self::Error> {
+        let nodes = page
+          .items
+          .into_iter()
+          .map(|item| <$node as std::convert::TryFrom<T>>::try_from(item).map_err(Into::into))
+          .collect::<Vec<anyhow::Result<$node>>>()
+          .into_iter()
+          .collect::<anyhow::Result<Vec<$node>>>()?;
+
+        let edges = nodes
+          .into_iter()
+          .map(|node| $edge {
+            cursor: crate::graphql::schema::pagination::encode_cursor(
+              node.created_at,
+              node.id,
+              &node.name,
+            ),
+            node,
+          })
+          .collect::<Vec<$edge>>();
+
+        let end_cursor = edges
+          .last()
+          .map(|edge| edge.cursor.clone());
+
+        Ok($connection {
+          edges,
+          page_info: crate::graphql::schema::pagination::PageInfo {
+            has_next_page: page.has_next_page,
+            end_cursor,
+          },
+        })
+      }
+    }
+  };
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use chrono::TimeZone;
+
+  fn cursor() -> DBCursor {
+    DBCursor {
+      created_at: Utc
+        .ymd(2021, 8, 4)
+        .and_hms_micro(12, 30, 15, 123_456),
+      id: Uuid::new_v4(),
+      name: String::from("Q3 | Budget"),
+    }
+  }
+
+  fn encode(cursor: &DBCursor) -> String {
+    encode_cursor(cursor.created_at, cursor.id, &cursor.name)
+  }
+
+  #[test]
+  fn cursors_round_trip() {
+    let cursor = cursor();
+
+    assert_eq!(decode_cursor(&encode(&cursor)).unwrap(), cursor);
+  }
+
+  #[test]
+  fn cursors_are_base64_created_at_id_and_name() {
+    let cursor = cursor();
+
+    let decoded = base64::decode(encode(&cursor)).unwrap();
+
+    assert_eq!(
+      String::from_utf8(decoded).unwrap(),
+      format!("2021-08-04T12:30:15.123456+00:00|{}|Q3 | Budget", cursor.id)
+    );
+  }
+
+  #[test]
+  fn cursors_can_have_an_empty_name() {
+    let mut cursor = cursor();
+    cursor.name = String::new();
+
+    assert_eq!(decode_cursor(&encode(&cursor)).unwrap(), cursor);
+  }
+
+  #[test]
+  fn cursors_in_other_timezones_decode_to_utc() {
+    let id = Uuid::new_v4();
+    let encoded = base64::encode(format!("2021-08-04T14:30:15+02:00|{}|Budget", id));
+
+    assert_eq!(
+      decode_cursor(&encoded).unwrap(),
+      DBCursor {
+        created_at: Utc
+          .ymd(2021, 8, 4)
+          .and_hms(12, 30, 15),
+        id,
+        name: String::from("Budget"),
+      }
+    );
+  }
+
+  #[test]
+  fn invalid_base64_is_rejected() {
+    assert!(decode_cursor("not a cursor!").is_err());
+    assert!(decode_cursor(&base64::encode([0xff, 0xfe])).is_err());
+  }
+
+  #[test]
+  fn cursors_without_a_separator_are_rejected() {
+    let id = Uuid::new_v4();
+
+    assert!(decode_cursor(&base64::encode("2021-08-04T12:30:15+00:00")).is_err());
+    assert!(decode_cursor(&base64::encode(format!("2021-08-04T12:30:15+00:00{}", id))).is_err());
+  }
+
+  // Cursors from before the name was part of them.
+  #[test]
+  fn cursors_without_a_name_are_rejected() {
+    let id = Uuid::new_v4();
+
+    assert!(decode_cursor(&base64::encode(format!("2021-08-04T12:30:15+00:00|{}", id))).is_err());
+  }
+
+  #[test]
+  fn cursors_with_bad_parts_are_rejected() {
+    let id = Uuid::new_v4();
+
+    assert!(decode_cursor(&base64::encode(format!("yesterday|{}|Budget", id))).is_err());
+    assert!(decode_cursor(&base64::encode("2021-08-04T12:30:15+00:00|42|Budget")).is_err());
+  }
+
+  #[test]
+  fn page_size_defaults_and_bounds() {
+    assert_eq!(
+      page_args(None, None).unwrap(),
+      (DEFAULT_PAGE_SIZE as i64, None)
+    );
+    assert_eq!(page_args(Some(0), None).unwrap(), (0, None));
+    assert_eq!(
+      page_args(Some(MAX_PAGE_SIZE), None).unwrap(),
+      (MAX_PAGE_SIZE as i64, None)
+    );
+    assert!(page_args(Some(MAX_PAGE_SIZE + 1), None).is_err());
+    assert!(page_args(Some(-1), None).is_err());
+  }
+
+  #[test]
+  fn page_args_decode_the_cursor() {
+    let cursor = cursor();
+    let after = encode(&cursor);
+
+    assert_eq!(
+      page_args(Some(10), Some(after)).unwrap(),
+      (10, Some(cursor))
+    );
+    assert!(page_args(Some(10), Some(String::from("not a cursor!"))).is_err());
+  }
+}
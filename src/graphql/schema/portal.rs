@@ -1,16 +1,27 @@
 use chrono::{DateTime, Utc};
-use juniper::{graphql_object, FieldError, FieldResult, GraphQLEnum, GraphQLObject, GraphQLUnion};
+use juniper::{
+  graphql_object, FieldResult, GraphQLEnum, GraphQLInputObject, GraphQLObject,
+  GraphQLUnion,
+};
 use serde_json;
+use std::convert::TryInto;
 use std::str::FromStr;
 use strum_macros::EnumString;
 use uuid::Uuid;
 
-// use super::Mutation;
+use super::pagination::{page_args, OrderBy};
+use super::portalview::EgressTypes;
+use super::org::Org;
+use super::user::User;
+use super::Mutation;
 use super::Query;
+use crate::errors::{field_error, ApiError};
 use crate::graphql::context::GQLContext;
-use crate::services::db::portal_service::DBPortal;
+use crate::graphql::loaders::ordered_results;
+use crate::services::db::portal_service::{DBNewPortal, DBPortal};
+use crate::services::email_service::send_invitation;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Portal {
   pub id: Uuid,
 
@@ -45,22 +56,38 @@ impl Portal {
     self.name.clone()
   }
 
-  fn org(&self) -> Uuid {
+  fn org_id(&self) -> Uuid {
     self.org
   }
 
+  async fn org(&self, ctx: &GQLContext) -> FieldResult<Org> {
+    ctx
+      .org_loader
+      .load(self.org)
+      .await
+      .map_err(field_error)
+  }
+
   fn owner_ids(&self) -> Vec<Uuid> {
     self
       .owner_ids
       .clone()
   }
 
+  async fn owners(&self, ctx: &GQLContext) -> FieldResult<Vec<User>> {
+    load_users(ctx, &self.owner_ids).await
+  }
+
   fn vendor_ids(&self) -> Vec<Uuid> {
     self
       .vendor_ids
       .clone()
   }
 
+  async fn vendors(&self, ctx: &GQLContext) -> FieldResult<Vec<User>> {
+    load_users(ctx, &self.vendor_ids).await
+  }
+
   fn created_at(&self) -> DateTime<Utc> {
     self.created_at
   }
@@ -78,6 +105,15 @@ impl Portal {
   }
 }
 
+async fn load_users(ctx: &GQLContext, user_ids: &[Uuid]) -> FieldResult<Vec<User>> {
+  let users_by_id = ctx
+    .user_loader
+    .load_many(user_ids.to_vec())
+    .await;
+
+  ordered_results(user_ids, users_by_id)
+}
+
 impl From<DBPortal> for Portal {
   fn from(db_portal: DBPortal) -> Self {
     Portal {
@@ -94,23 +130,216 @@ impl From<DBPortal> for Portal {
   }
 }
 
+connection!(PortalConnection, PortalEdge, Portal);
+
+#[derive(GraphQLInputObject, Debug, Serialize, Deserialize)]
+pub struct NewPortal {
+  pub org: Uuid,
+
+  pub name: String,
+
+  pub owner_ids: Option<Vec<Uuid>>,
+
+  pub vendor_ids: Option<Vec<Uuid>>,
+}
+
+#[derive(GraphQLInputObject, Debug, Serialize, Deserialize)]
+pub struct UpdatePortal {
+  pub id: Uuid,
+
+  pub name: Option<String>,
+
+  pub owner_ids: Option<Vec<Uuid>>,
+
+  pub vendor_ids: Option<Vec<Uuid>>,
+}
+
+#[derive(GraphQLInputObject, Debug, Default, Serialize, Deserialize)]
+pub struct PortalFilter {
+  pub org_id: Option<Uuid>,
+
+  #[graphql(description = "Only portals the requesting user is an owner, or a vendor, of")]
+  pub egress: Option<EgressTypes>,
+
+  #[graphql(description = "Case insensitive")]
+  pub name_contains: Option<String>,
+}
+
+#[derive(GraphQLObject, Debug, Clone)]
+#[graphql(context = GQLContext)]
+pub struct PortalInvitation {
+  pub portal: Portal,
+
+  pub user: User,
+}
+
+// Just enough to catch typos, SES rejects anything it can't deliver to.
+fn is_email(email: &str) -> bool {
+  match email.split_once('@') {
+    Some((local, domain)) => {
+      !local.is_empty()
+        && domain.contains('.')
+        && !domain.contains('@')
+        && !email.contains(char::is_whitespace)
+    }
+    None => false,
+  }
+}
+
 impl Query {
   pub async fn portal_impl(ctx: &GQLContext, portal_id: Uuid) -> FieldResult<Portal> {
     ctx
       .db
-      .get_portal(portal_id)
+      .get_auth0_user_portal(&ctx.auth0_user_id, portal_id)
       .await
       .map(|db_portal| db_portal.into())
-      .map_err(FieldError::from)
+      .map_err(field_error)
   }
 
   // Get all portals associated to a user
-  pub async fn user_portals_impl(ctx: &GQLContext) -> FieldResult<Vec<Portal>> {
-    ctx.db.get_auth0_user_portals(&ctx.auth0_user_id)
+  pub async fn user_portals_impl(
+    ctx: &GQLContext,
+    filter: Option<PortalFilter>,
+    order_by: Option<OrderBy>,
+    first: Option<i32>,
+    after: Option<String>,
+  ) -> FieldResult<PortalConnection> {
+    let (first, after) = page_args(first, after)?;
+
+    ctx
+      .db
+      .get_auth0_user_portals(
+        &ctx.auth0_user_id,
+        filter
+          .unwrap_or_default()
+          .into(),
+        order_by.unwrap_or_default(),
+        first,
+        after,
+      )
+      .await
+      .and_then(|page| page.try_into())
+      .map_err(field_error)
+  }
+}
+
+impl Mutation {
+  pub async fn create_portal_impl(ctx: &GQLContext, new_portal: NewPortal) -> FieldResult<Portal> {
+    let user = ctx
+      .db
+      .get_user_by_auth0_id(&ctx.auth0_user_id)
+      .await
+      .map_err(field_error)?;
+
+    let mut db_new_portal: DBNewPortal = new_portal.into();
+
+    // The user creating the portal is always one of its owners.
+    if !db_new_portal
+      .owner_ids
+      .contains(&user.id)
+    {
+      db_new_portal
+        .owner_ids
+        .push(user.id);
+    }
+
+    ctx
+      .db
+      .create_portal(&ctx.auth0_user_id, db_new_portal)
+      .await
+      .map(|db_portal| db_portal.into())
+      .map_err(field_error)
+  }
+
+  pub async fn update_portal_impl(
+    ctx: &GQLContext,
+    update_portal: UpdatePortal,
+  ) -> FieldResult<Portal> {
+    ctx
+      .db
+      .update_portal(&ctx.auth0_user_id, update_portal.into())
+      .await
+      .map(|db_portal| db_portal.into())
+      .map_err(field_error)
+  }
+
+  pub async fn invite_user_to_portal_impl(
+    ctx: &GQLContext,
+    email: String,
+    portal_id: Uuid,
+    egress: EgressTypes,
+  ) -> FieldResult<PortalInvitation> {
+    let email = email.trim();
+
+    if !is_email(email) {
+      return Err(field_error(ApiError::Validation(format!(
+        "Not an email address: {}",
+        email
+      ))));
+    }
+
+    // Only members of the portal can invite others to it.
+    ctx
+      .db
+      .get_auth0_user_portal(&ctx.auth0_user_id, portal_id)
+      .await
+      .map_err(field_error)?;
+
+    let inviter = ctx
+      .db
+      .get_user_by_auth0_id(&ctx.auth0_user_id)
+      .await
+      .map_err(field_error)?;
+
+    let (db_portal, db_user, invitation) = ctx
+      .db
+      .invite_user_to_portal(
+        &ctx.auth0_user_id,
+        email,
+        portal_id,
+        &egress.to_string(),
+        ctx
+          .invitation_tokens
+          .expires_at(),
+      )
+      .await
+      .map_err(field_error)?;
+
+    // Users that have already signed up don't need a token, they only have to log in.
+    let token = match invitation {
+      Some(invitation) => Some(
+        ctx
+          .invitation_tokens
+          .sign(invitation.id, invitation.expires_at)
+          .map_err(field_error)?,
+      ),
+      None => None,
+    };
+
+    // The user stays in the portal if this fails, inviting them again resends the email.
+    send_invitation(
+      ctx.email.as_ref(),
+      &db_user.email,
+      &db_portal.name,
+      &inviter.name,
+      &egress.to_string(),
+      token.as_deref(),
+    )
     .await
-    .map(|db_portals| {
-      db_portals.into_iter().map(|p| p.into()).collect()
+    .map_err(field_error)?;
+
+    Ok(PortalInvitation {
+      portal: db_portal.into(),
+      user: db_user.into(),
     })
-    .map_err(FieldError::from)
+  }
+
+  pub async fn delete_portal_impl(ctx: &GQLContext, portal_id: Uuid) -> FieldResult<Portal> {
+    ctx
+      .db
+      .delete_portal(&ctx.auth0_user_id, portal_id)
+      .await
+      .map(|db_portal| db_portal.into())
+      .map_err(field_error)
   }
 }
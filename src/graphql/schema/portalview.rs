@@ -1,17 +1,41 @@
 use uuid::Uuid;
 use chrono::{DateTime, Utc};
-use juniper::{graphql_object, FieldError, FieldResult, GraphQLInputObject};
+use juniper::{graphql_object, FieldResult, GraphQLEnum, GraphQLInputObject};
+use std::convert::{TryFrom, TryInto};
+use strum_macros::{EnumString, ToString};
 
+use super::conversion::parse_column;
+use super::pagination::page_args;
 use super::Mutation;
 use super::Query;
+use crate::errors::field_error;
 use crate::graphql::context::GQLContext;
 
 use crate::services::db::portalview_service::DBPortalView;
 
+// Stored in the db as "owner" and "vendor".
+#[derive(
+  Debug, Clone, Copy, PartialEq, Serialize, Deserialize, GraphQLEnum, EnumString, ToString,
+)]
+#[strum(serialize_all = "snake_case")]
+pub enum EgressTypes {
+  Owner,
+  Vendor,
+}
+
+// Stored in the db as "public" and "private".
+#[derive(
+  Debug, Clone, Copy, PartialEq, Serialize, Deserialize, GraphQLEnum, EnumString, ToString,
+)]
+#[strum(serialize_all = "snake_case")]
+pub enum AccessTypes {
+  Public,
+  Private,
+}
 
 // Portal View
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PortalView {
   pub id: Uuid,
 
@@ -20,9 +44,9 @@ pub struct PortalView {
 
   pub name: String,
 
-  pub egress: String,
+  pub egress: EgressTypes,
 
-  pub access: String,
+  pub access: AccessTypes,
 
   #[serde(rename = "createdAt")]
   pub created_at: DateTime<Utc>,
@@ -51,12 +75,12 @@ impl PortalView {
     self.name.clone()
   }
 
-  fn egress(&self) -> String {
-    self.egress.clone()
+  fn egress(&self) -> EgressTypes {
+    self.egress
   }
 
-  fn access(&self) -> String {
-    self.access.clone()
+  fn access(&self) -> AccessTypes {
+    self.access
   }
 
   fn created_at(&self) -> DateTime<Utc> {
@@ -76,31 +100,119 @@ impl PortalView {
   }
 }
 
-impl From<DBPortalView> for PortalView {
-  fn from(db_portalview: DBPortalView) -> Self {
-    PortalView {
+impl TryFrom<DBPortalView> for PortalView {
+  type Error = anyhow::Error;
+
+  fn try_from(db_portalview: DBPortalView) -> Result<Self, Self::Error> {
+    let egress = parse_column("PortalView", db_portalview.id, "egress", &db_portalview.egress)?;
+
+    let access = parse_column("PortalView", db_portalview.id, "access", &db_portalview.access)?;
+
+    Ok(PortalView {
       id: db_portalview.id,
       portal_id: db_portalview.portal_id,
       name: db_portalview.name,
-      egress: db_portalview.egress,
-      access: db_portalview.access,
+      egress,
+      access,
       created_at: db_portalview.created_at,
       created_by: db_portalview.created_by,
       updated_at: db_portalview.updated_at,
       updated_by: db_portalview.updated_by,
-    }
+    })
   }
 }
 
+connection!(PortalViewConnection, PortalViewEdge, PortalView);
+
+#[derive(GraphQLInputObject, Debug, Serialize, Deserialize)]
+pub struct NewPortalView {
+  pub portal_id: Uuid,
+
+  pub name: String,
+
+  pub egress: EgressTypes,
+
+  pub access: AccessTypes,
+}
+
+#[derive(GraphQLInputObject, Debug, Serialize, Deserialize)]
+pub struct UpdatePortalView {
+  pub id: Uuid,
+
+  pub name: Option<String>,
+
+  pub egress: Option<EgressTypes>,
+
+  pub access: Option<AccessTypes>,
+}
+
 impl Query {
-  pub async fn portalviews_impl(ctx: &GQLContext, portal_id: Uuid) -> FieldResult<Vec<PortalView>> {
+  pub async fn portalview_impl(ctx: &GQLContext, portal_view_id: Uuid) -> FieldResult<PortalView> {
     ctx
       .db
-      .get_portal_views(portal_id)
+      .get_auth0_user_portal_view(&ctx.auth0_user_id, portal_view_id)
       .await
-      .map(|db_portalviews| {
-        db_portalviews.into_iter().map(|pv| pv.into()).collect()
-      })
-      .map_err(FieldError::from)
+      .and_then(PortalView::try_from)
+      .map_err(field_error)
   }
-}
\ No newline at end of file
+
+  pub async fn portalviews_impl(
+    ctx: &GQLContext,
+    portal_id: Uuid,
+    first: Option<i32>,
+    after: Option<String>,
+  ) -> FieldResult<PortalViewConnection> {
+    let (first, after) = page_args(first, after)?;
+
+    ctx
+      .db
+      .get_auth0_user_portal(&ctx.auth0_user_id, portal_id)
+      .await
+      .map_err(field_error)?;
+
+    ctx
+      .db
+      .get_portal_views(portal_id, first, after)
+      .await
+      .and_then(|page| page.try_into())
+      .map_err(field_error)
+  }
+}
+
+impl Mutation {
+  pub async fn create_portalview_impl(
+    ctx: &GQLContext,
+    new_portalview: NewPortalView,
+  ) -> FieldResult<PortalView> {
+    ctx
+      .db
+      .create_portal_view(&ctx.auth0_user_id, new_portalview.into())
+      .await
+      .and_then(PortalView::try_from)
+      .map_err(field_error)
+  }
+
+  pub async fn update_portalview_impl(
+    ctx: &GQLContext,
+    update_portalview: UpdatePortalView,
+  ) -> FieldResult<PortalView> {
+    ctx
+      .db
+      .update_portal_view(&ctx.auth0_user_id, update_portalview.into())
+      .await
+      .and_then(PortalView::try_from)
+      .map_err(field_error)
+  }
+
+  pub async fn delete_portalview_impl(
+    ctx: &GQLContext,
+    portal_view_id: Uuid,
+  ) -> FieldResult<PortalView> {
+    ctx
+      .db
+      .delete_portal_view(&ctx.auth0_user_id, portal_view_id)
+      .await
+      .and_then(PortalView::try_from)
+      .map_err(field_error)
+  }
+}
@@ -1,14 +1,19 @@
+use anyhow::Result;
 use chrono::{DateTime, Utc};
 use juniper::{
-  FieldError, FieldResult, GraphQLEnum, GraphQLInputObject, GraphQLObject, GraphQLUnion,
+  FieldResult, GraphQLEnum, GraphQLInputObject, GraphQLObject, GraphQLUnion,
 };
+use std::convert::TryFrom;
 use std::str::FromStr;
 use strum_macros::{EnumString, ToString};
 use uuid::Uuid;
 
+use crate::errors::field_error;
+use crate::graphql::authorization::Permission;
 use crate::graphql::context::GQLContext;
 use crate::services::db::role_service::{DBNewRole, DBRole};
 
+use super::conversion::{parse_column, report_decode_failure};
 use super::Mutation;
 use super::Query;
 
@@ -21,7 +26,7 @@ pub enum RoleTypes {
 
 // Perms
 
-#[derive(GraphQLObject, Debug, Serialize, Deserialize)]
+#[derive(GraphQLObject, Debug, Clone, Serialize, Deserialize)]
 pub struct SystemPermissions {
   create_org: bool,
   view_org: bool,
@@ -29,7 +34,7 @@ pub struct SystemPermissions {
   delete_org: bool,
 }
 
-#[derive(GraphQLObject, Debug, Serialize, Deserialize)]
+#[derive(GraphQLObject, Debug, Clone, Serialize, Deserialize)]
 pub struct OrgPermissions {
   create_portal: bool,
   view_all_portals: bool,
@@ -43,18 +48,18 @@ pub struct OrgPermissions {
   edit_org_users: bool,
 }
 
-#[derive(GraphQLObject, Debug, Serialize, Deserialize)]
+#[derive(GraphQLObject, Debug, Clone, Serialize, Deserialize)]
 pub struct PortalPermissions {
   view_portal: bool,
   edit_portal: bool,
 }
 
-#[derive(GraphQLObject, Debug, Serialize, Deserialize)]
+#[derive(GraphQLObject, Debug, Clone, Serialize, Deserialize)]
 pub struct EmptyPermissions {
   role_type: String,
 }
 
-#[derive(Debug, GraphQLUnion, Serialize, Deserialize)]
+#[derive(Debug, Clone, GraphQLUnion, Serialize, Deserialize)]
 pub enum RolePerms {
   System(SystemPermissions),
   Org(OrgPermissions),
@@ -62,6 +67,53 @@ pub enum RolePerms {
   Empty(EmptyPermissions),
 }
 
+impl RolePerms {
+  pub fn from_db_role(db_role: &DBRole) -> Result<Self> {
+    let perms = match db_role
+      .role_type
+      .as_str()
+    {
+      "System" => RolePerms::System(serde_json::from_value(db_role.perms.clone())?),
+      "Org" => RolePerms::Org(serde_json::from_value(db_role.perms.clone())?),
+      "Portal" => RolePerms::Portal(serde_json::from_value(db_role.perms.clone())?),
+      &_ => RolePerms::Empty(EmptyPermissions {
+        role_type: String::from("Empty"),
+      }),
+    };
+
+    Ok(perms)
+  }
+
+  pub fn grants(&self, permission: Permission) -> bool {
+    match self {
+      RolePerms::System(perms) => match permission {
+        Permission::CreateOrg => perms.create_org,
+        Permission::ViewOrg => perms.view_org,
+        Permission::EditOrg => perms.edit_org,
+        Permission::DeleteOrg => perms.delete_org,
+        _ => false,
+      },
+      RolePerms::Org(perms) => match permission {
+        Permission::CreatePortal => perms.create_portal,
+        Permission::ViewAllPortals => perms.view_all_portals,
+        Permission::ViewMemberPortals => perms.view_member_portals,
+        Permission::EditOrg => perms.edit_org,
+        Permission::DeleteOrg => perms.delete_org,
+        Permission::AddOrgUser => perms.add_org_user,
+        Permission::DeleteOrgUser => perms.delete_org_user,
+        Permission::EditOrgUsers => perms.edit_org_users,
+        _ => false,
+      },
+      RolePerms::Portal(perms) => match permission {
+        Permission::ViewPortal => perms.view_portal,
+        Permission::EditPortal => perms.edit_portal,
+        _ => false,
+      },
+      RolePerms::Empty(_) => false,
+    }
+  }
+}
+
 #[derive(GraphQLObject, Debug, Serialize, Deserialize)]
 pub struct Role {
   pub id: Uuid,
@@ -83,40 +135,23 @@ pub struct Role {
   pub updated_by: Uuid,
 }
 
-impl From<DBRole> for Role {
-  fn from(db_role: DBRole) -> Self {
-    let perms = match db_role
-      .role_type
-      .as_str()
-    {
-      "System" => {
-        let json: SystemPermissions =
-          serde_json::from_value(db_role.perms).expect("Unable to deserialize SystemPermissions");
-        RolePerms::System(json)
-      }
-      "Org" => {
-        let json: OrgPermissions =
-          serde_json::from_value(db_role.perms).expect("Unable to deserialize OrgPermissions");
-        RolePerms::Org(json)
-      }
-      "Portal" => {
-        let json: PortalPermissions =
-          serde_json::from_value(db_role.perms).expect("Unable to deserialize PortalPermissions");
-        RolePerms::Portal(json)
-      }
-      &_ => RolePerms::Empty(EmptyPermissions {
-        role_type: String::from("Empty"),
-      }),
-    };
+impl TryFrom<DBRole> for Role {
+  type Error = anyhow::Error;
 
-    let role_type = RoleTypes::from_str(
-      db_role
-        .role_type
-        .as_str(),
-    )
-    .expect("Unable to convert role_type string to enum variant");
+  fn try_from(db_role: DBRole) -> Result<Self, Self::Error> {
+    let role_type = parse_column("Role", db_role.id, "role_type", &db_role.role_type)?;
+
+    let perms = RolePerms::from_db_role(&db_role).unwrap_or_else(|err| {
+      report_decode_failure("Role", db_role.id, err);
 
-    Role {
+      RolePerms::Empty(EmptyPermissions {
+        role_type: db_role
+          .role_type
+          .clone(),
+      })
+    });
+
+    Ok(Role {
       id: db_role.id,
       role_type,
       perms,
@@ -124,7 +159,7 @@ impl From<DBRole> for Role {
       created_by: db_role.created_by,
       updated_at: db_role.updated_at,
       updated_by: db_role.updated_by,
-    }
+    })
   }
 }
 
@@ -153,8 +188,8 @@ impl Query {
       .db
       .get_role(role_id)
       .await
-      .map(|db_role| db_role.into())
-      .map_err(FieldError::from)
+      .and_then(Role::try_from)
+      .map_err(field_error)
   }
 }
 
@@ -164,7 +199,7 @@ impl Mutation {
       .db
       .create_role(&ctx.auth0_user_id, new_role.into())
       .await
-      .map(|role| -> Role { role.into() })
-      .map_err(FieldError::from)
+      .and_then(Role::try_from)
+      .map_err(field_error)
   }
 }
@@ -1,17 +1,19 @@
 use crate::services::db::user_service::DBUser;
 use chrono::{DateTime, Utc};
-use juniper::{graphql_object, FieldError, FieldResult, GraphQLInputObject};
+use juniper::{graphql_object, FieldResult, GraphQLInputObject};
 use uuid::Uuid;
 
 use super::Mutation;
 use super::Query;
+use crate::errors::field_error;
 use crate::graphql::context::GQLContext;
+use crate::graphql::loaders::ordered_results;
 
 use crate::graphql::schema::Org;
 
 // User
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct User {
   pub id: Uuid,
 
@@ -75,20 +77,13 @@ impl User {
     self.org_ids.clone()
   }
 
-  pub async fn orgs(&self, context: &GQLContext) -> Vec<Org> {
+  pub async fn orgs(&self, context: &GQLContext) -> FieldResult<Vec<Org>> {
     let org_map = context
       .org_loader
       .load_many(self.org_ids.clone())
       .await;
 
-    let orgs = org_map
-      .into_iter()
-      .fold(vec![], |mut acc, (_, org)| {
-        acc.push(org);
-        acc
-      });
-
-    orgs
+    ordered_results(&self.org_ids, org_map)
   }
 
   fn role_ids(&self) -> Vec<Uuid> {
@@ -174,14 +169,15 @@ impl Query {
       .get_user(user_id)
       .await
       .map(|db_user| -> User { db_user.into() })
-      .map_err(FieldError::from)
+      .map_err(field_error)
   }
 
   pub async fn current_user_impl(ctx: &GQLContext) -> FieldResult<User> {
     let user_exists = ctx
       .db
       .user_exists(&ctx.auth0_user_id)
-      .await?;
+      .await
+      .map_err(field_error)?;
 
     if user_exists {
       ctx
@@ -189,7 +185,7 @@ impl Query {
         .get_user_by_auth0_id(&ctx.auth0_user_id)
         .await
         .map(|db_user| -> User { db_user.into() })
-        .map_err(FieldError::from)
+        .map_err(field_error)
     } else {
       let mut auth_api = ctx
         .auth0_api
@@ -198,7 +194,8 @@ impl Query {
 
       let auth0user = auth_api
         .get_auth0_user(&ctx.auth0_user_id)
-        .await?;
+        .await
+        .map_err(field_error)?;
 
       let new_user = NewUser {
         name: auth0user.name,
@@ -212,7 +209,8 @@ impl Query {
       let db_user = ctx
         .db
         .create_user(&ctx.auth0_user_id, new_user.into())
-        .await?;
+        .await
+        .map_err(field_error)?;
 
       Ok(db_user.into())
     }
@@ -228,7 +226,7 @@ impl Mutation {
       .create_user(&ctx.auth0_user_id, new_user.into())
       .await
       .map(|db_user| -> User { db_user.into() })
-      .map_err(FieldError::from)
+      .map_err(field_error)
   }
 
   pub async fn update_user_impl(ctx: &GQLContext, update_user: UpdateUser) -> FieldResult<User> {
@@ -237,6 +235,42 @@ impl Mutation {
       .update_user(&ctx.auth0_user_id, update_user.into())
       .await
       .map(|db_user| -> User { db_user.into() })
-      .map_err(FieldError::from)
+      .map_err(field_error)
+  }
+
+  pub async fn accept_invitation_impl(ctx: &GQLContext, token: String) -> FieldResult<User> {
+    let invitation_id = ctx
+      .invitation_tokens
+      .verify(&token)
+      .map_err(field_error)?;
+
+    // A caller without a user yet takes over the invited user, filled in from their Auth0 profile.
+    let profile = if ctx
+      .db
+      .user_exists(&ctx.auth0_user_id)
+      .await
+      .map_err(field_error)?
+    {
+      None
+    } else {
+      let mut auth_api = ctx
+        .auth0_api
+        .lock()
+        .await;
+
+      let auth0user = auth_api
+        .get_auth0_user(&ctx.auth0_user_id)
+        .await
+        .map_err(field_error)?;
+
+      Some(auth0user)
+    };
+
+    ctx
+      .db
+      .accept_invitation(&ctx.auth0_user_id, invitation_id, profile.as_ref())
+      .await
+      .map(|db_user| -> User { db_user.into() })
+      .map_err(field_error)
   }
 }
@@ -18,6 +18,7 @@ extern crate rusoto_ses;
 
 extern crate base64;
 
+mod errors;
 mod graphql;
 mod middleware;
 mod models;
@@ -35,17 +36,19 @@ use actix_web::{get, middleware as actix_middleware, web, App, Error, HttpRespon
 // use base64::encode;
 use dotenv::dotenv;
 use futures::lock::Mutex;
-use jsonwebtoken::DecodingKey;
 use listenfd::ListenFd;
 use sqlx::postgres::PgPoolOptions;
 
 use crate::graphql::{graphql_routes, schema as graphql_schema};
 use crate::state::State;
 
+use crate::middleware::auth::AuthConfig;
 use crate::services::auth0_service::Auth0Service;
-
-// NOTE: I don't know if this will always be length of 270, but this is working for now..
-pub static KEY: [u8; 270] = *include_bytes!("../auth0.der");
+use crate::services::change_broker::ChangeBroker;
+use crate::services::email_service::{EmailService, SesEmailService};
+use crate::services::invitation_token_service::InvitationTokens;
+use crate::services::layout_service::LayoutConfig;
+use crate::services::jwks_service::JwksService;
 
 #[get("/health")]
 pub async fn get_health() -> Result<HttpResponse, Error> {
@@ -84,23 +87,68 @@ async fn main() -> std::io::Result<()> {
   let state = State::new(pool.clone());
   let auth_service = Arc::new(Mutex::new(Auth0Service::new()));
 
-  let mut listenfd = ListenFd::from_env();
-  let mut server = HttpServer::new(move || {
-    let client_secret =
-      std::env::var("AUTH0_API_SIGNING_SECRET").expect("Unable to get AUTH0_API_SIGNING_SECRET.");
+  let auth_config = match AuthConfig::from_env() {
+    Ok(config) => web::Data::new(config),
+    Err(msg) => {
+      eprintln!("Invalid auth config: {}", msg);
+      std::process::exit(1);
+    }
+  };
+  let change_broker = web::Data::new(ChangeBroker::new());
+  let layout_config = match LayoutConfig::from_env() {
+    Ok(config) => web::Data::new(config),
+    Err(msg) => {
+      eprintln!("Invalid block layout config: {}", msg);
+      std::process::exit(1);
+    }
+  };
+  let email_service: Arc<dyn EmailService> = match SesEmailService::from_env() {
+    Ok(email_service) => Arc::new(email_service),
+    Err(msg) => {
+      eprintln!("Invalid email config: {}", msg);
+      std::process::exit(1);
+    }
+  };
+  let email_service = web::Data::new(email_service);
+  let invitation_tokens = match InvitationTokens::from_env() {
+    Ok(invitation_tokens) => web::Data::new(invitation_tokens),
+    Err(msg) => {
+      eprintln!("Invalid invitation config: {}", msg);
+      std::process::exit(1);
+    }
+  };
 
-    // let b64_client_secret = encode(&client_secret);
+  let listener_broker = change_broker.clone();
+  let listener_db_url = db_url.clone();
+  actix_web::rt::spawn(async move {
+    listener_broker
+      .listen(&listener_db_url)
+      .await
+  });
 
-    let decoding_key = DecodingKey::from_secret(client_secret.as_bytes()).into_static();
+  println!("Fetching JWKS");
+  let jwks_service = web::Data::new(JwksService::from_env());
 
+  // Not fatal, the keys will be fetched again the first time a token comes in.
+  if let Err(err) = jwks_service
+    .refresh()
+    .await
+  {
+    println!("Unable to fetch JWKS: {:?}", err);
+  }
+
+  let mut listenfd = ListenFd::from_env();
+  let mut server = HttpServer::new(move || {
     App::new()
       .data(state.clone())
       .data(graphql_schema::create_schema())
       .app_data(web::Data::new(auth_service.clone()))
-      .app_data(decoding_key)
-      // .app_data(decoding_key.clone())
-      // .app_data(DecodingKey::from_secret(b64_client_secret.as_bytes()))
-      // .app_data(DecodingKey::from_secret())
+      .app_data(jwks_service.clone())
+      .app_data(auth_config.clone())
+      .app_data(change_broker.clone())
+      .app_data(layout_config.clone())
+      .app_data(email_service.clone())
+      .app_data(invitation_tokens.clone())
       // .wrap(actix_middleware::Logger::new("%r %s size:%b time in ms:%D"))
       .wrap(
         Cors::default()
@@ -111,6 +159,7 @@ async fn main() -> std::io::Result<()> {
           .supports_credentials(),
       )
       .service(graphql_routes::get_graphql_routes())
+      .service(graphql_routes::get_graphql_subscription_routes())
       .service(graphql_routes::get_graphql_dev_routes())
       .service(get_health)
   });
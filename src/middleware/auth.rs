@@ -1,47 +1,182 @@
-use actix_web::dev::ServiceRequest;
-use actix_web::{Error};
+use actix_web::dev::{self, ServiceRequest};
+use actix_web::{error, web, Error, FromRequest, HttpMessage, HttpRequest};
 use actix_web_httpauth::extractors::bearer::{BearerAuth, Config};
 use actix_web_httpauth::extractors::{AuthenticationError};
-use jsonwebtoken::{decode, DecodingKey, Validation};
-// use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
-
-#[derive(Debug, Serialize, Deserialize)]
-struct Claims {
-  sub: String,
-  exp: usize,
-  aud: Vec<String>,
+use anyhow::anyhow;
+use futures::future::{err, ok, Ready};
+use jsonwebtoken::{decode, decode_header, Algorithm, TokenData, Validation};
+
+use crate::services::jwks_service::JwksService;
+
+const DEFAULT_LEEWAY_SECS: u64 = 120;
+
+// The verified claims of the request's access token. The validator puts these into the request
+// extensions, so handlers can pull them out without having to decode the token again.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Claims {
+  pub sub: String,
+
+  pub exp: usize,
+
+  // Space separated, as Auth0 issues them.
+  #[serde(default)]
+  pub scope: Option<String>,
+
+  // Only present when RBAC is enabled for the Auth0 API.
+  #[serde(default)]
+  pub permissions: Vec<String>,
+}
+
+impl Claims {
+  pub fn scopes(&self) -> Vec<&str> {
+    self
+      .scope
+      .as_deref()
+      .map(|scope| {
+        scope
+          .split_whitespace()
+          .collect()
+      })
+      .unwrap_or_default()
+  }
+
+  pub fn has_scope(&self, scope: &str) -> bool {
+    self
+      .scopes()
+      .contains(&scope)
+      || self
+        .permissions
+        .iter()
+        .any(|perm| perm == scope)
+  }
+}
+
+impl FromRequest for Claims {
+  type Error = actix_web::Error;
+  type Future = Ready<Result<Self, Self::Error>>;
+  type Config = ();
+
+  fn from_request(req: &HttpRequest, _payload: &mut dev::Payload) -> Self::Future {
+    match req
+      .extensions()
+      .get::<Claims>()
+    {
+      Some(claims) => ok(claims.clone()),
+      None => err(error::ErrorUnauthorized("Missing verified access token")),
+    }
+  }
+}
+
+// Everything a token is validated against, besides the signing key.
+#[derive(Debug, Clone)]
+pub struct AuthConfig {
+  pub audiences: Vec<String>,
+  pub issuer: String,
+  pub leeway: u64,
+}
+
+impl AuthConfig {
+  // AUTH0_AUDIENCES is a comma separated list. If PORTALS_ENV is set (ie. "staging"), an
+  // AUTH0_AUDIENCES_STAGING list will be used instead when present, so one binary can be
+  // deployed to every environment. AUTH0_ISSUER is required, AUTH0_LEEWAY is in seconds.
+  pub fn from_env() -> Result<Self, String> {
+    let config = AuthConfig::parse(|name| std::env::var(name).ok())?;
+
+    println!(
+      "auth: issuer {}, audiences {:?}",
+      config.issuer, config.audiences
+    );
+
+    Ok(config)
+  }
+
+  // Reads the config through var, which looks up an env var by name.
+  fn parse(var: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+    let env_audiences = var("PORTALS_ENV")
+      .and_then(|portals_env| var(&format!("AUTH0_AUDIENCES_{}", portals_env.to_uppercase())));
+
+    let audiences = env_audiences
+      .or_else(|| var("AUTH0_AUDIENCES"))
+      .unwrap_or_default()
+      .split(',')
+      .map(|aud| aud.trim().to_string())
+      .filter(|aud| !aud.is_empty())
+      .collect::<Vec<String>>();
+
+    if audiences.is_empty() {
+      return Err(String::from("No JWT audiences configured"));
+    }
+
+    let issuer = var("AUTH0_ISSUER").ok_or_else(|| String::from("AUTH0_ISSUER is not set"))?;
+
+    let leeway = match var("AUTH0_LEEWAY") {
+      Some(leeway) => leeway
+        .parse::<u64>()
+        .map_err(|_| {
+          format!(
+            "AUTH0_LEEWAY must be a number of seconds, got \"{}\"",
+            leeway
+          )
+        })?,
+      None => DEFAULT_LEEWAY_SECS,
+    };
+
+    Ok(AuthConfig {
+      audiences,
+      issuer,
+      leeway,
+    })
+  }
+
+  pub fn validation(&self) -> Validation {
+    let mut validation = Validation {
+      leeway: self.leeway,
+      iss: Some(self.issuer.clone()),
+      ..Validation::new(Algorithm::RS256)
+    };
+
+    validation.set_audience(&self.audiences);
+
+    validation
+  }
+}
+
+async fn decode_token(
+  token: &str,
+  jwks: &JwksService,
+  auth_config: &AuthConfig,
+) -> anyhow::Result<TokenData<Claims>> {
+  let header = decode_header(token)?;
+
+  let kid = header
+    .kid
+    .ok_or_else(|| anyhow!("Token header is missing a kid"))?;
+
+  let key = jwks
+    .get_key(&kid)
+    .await?;
+
+  decode::<Claims>(token, &key, &auth_config.validation()).map_err(anyhow::Error::from)
 }
 
 pub async fn validator(req: ServiceRequest, credentials: BearerAuth) -> Result<ServiceRequest, Error> {
-  let key = req.app_data::<DecodingKey>().unwrap();
-  // let cs = "ek5BUjdVSVVCWmk2dC1EbU9IVndwWmpSUHBoVTN5LXVJUG9nQnpybTVHN3RYczVjdGRRRm5SaXVSOXJiSzRDdg==".as_bytes();
-  // let cs = "zNAR7UIUBZi6t-DmOHVwpZjRPphU3y-uIPogBzrm5G7tXs5ctdQFnRiuR9rbK4Cv".as_bytes();
-  // let key = DecodingKey::from_secret(cs);
-  // let client_secret = std::env::var("AUTH0_CLIENT_SECRET").expect("Unable to get AUTH0_CLIENT_SECRET.");
-  // println!("client_secret: {}", client_secret);
-  // let b64_client_secret = base64::encode(client_secret);
-  // println!("b64_client_secret: {}", b64_client_secret);
-  // let key = DecodingKey::from_secret(b64_client_secret.as_bytes());
-
-
-  // println!("creds: {:?}", credentials.token());
-
-  let mut validation = Validation {
-    // algorithms: vec![Algorithm::HS256],
-    leeway: 120,
-    ..Validation::default()
-  };
-
-  // TODO: Remove these hardcoded audiences. Pull from env?
-  validation.set_audience(&[
-    "http://localhost:8088",
-    // "https://torus-rocks.auth0.com/userinfo",
-    // "a7781863-554e-4849-9be4-020aa067a2cc"
-    "https://local.portals-dev.rocks"
-  ]);
-
-  match decode::<Claims>(credentials.token(), &key, &validation) {
-    Ok(_) => Ok(req),
+  let jwks = req
+    .app_data::<web::Data<JwksService>>()
+    .cloned()
+    .ok_or_else(|| error::ErrorInternalServerError("JwksService missing from app data"))?;
+
+  let auth_config = req
+    .app_data::<web::Data<AuthConfig>>()
+    .cloned()
+    .ok_or_else(|| error::ErrorInternalServerError("AuthConfig missing from app data"))?;
+
+  match decode_token(credentials.token(), &jwks, &auth_config).await {
+    Ok(token_data) => {
+      req
+        .extensions_mut()
+        .insert(token_data.claims);
+      Ok(req)
+    },
     Err(err) => {
       println!("valdation err: {:?}", err);
       // TODO: figure out how to better handle this error;
@@ -222,3 +357,84 @@ pub async fn validator(req: ServiceRequest, credentials: BearerAuth) -> Result<S
 //     }
 //   }
 // }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::collections::HashMap;
+
+  fn parse(vars: &[(&str, &str)]) -> Result<AuthConfig, String> {
+    let vars: HashMap<String, String> = vars
+      .iter()
+      .map(|(name, value)| (name.to_string(), value.to_string()))
+      .collect();
+
+    AuthConfig::parse(|name| {
+      vars
+        .get(name)
+        .cloned()
+    })
+  }
+
+  #[test]
+  fn portals_env_picks_its_own_audiences() {
+    let config = parse(&[
+      ("PORTALS_ENV", "staging"),
+      (
+        "AUTH0_AUDIENCES_STAGING",
+        "https://staging.example.com, https://ws.example.com",
+      ),
+      ("AUTH0_AUDIENCES", "https://example.com"),
+      ("AUTH0_ISSUER", "https://example.auth0.com/"),
+    ])
+    .unwrap();
+
+    assert_eq!(
+      config.audiences,
+      vec!["https://staging.example.com", "https://ws.example.com"]
+    );
+  }
+
+  #[test]
+  fn audiences_default_to_the_shared_list() {
+    let config = parse(&[
+      ("PORTALS_ENV", "production"),
+      ("AUTH0_AUDIENCES", "https://example.com"),
+      ("AUTH0_ISSUER", "https://example.auth0.com/"),
+    ])
+    .unwrap();
+
+    assert_eq!(config.audiences, vec!["https://example.com"]);
+    assert_eq!(config.leeway, DEFAULT_LEEWAY_SECS);
+  }
+
+  #[test]
+  fn audiences_are_required() {
+    assert!(parse(&[("AUTH0_ISSUER", "https://example.auth0.com/")]).is_err());
+    assert!(parse(&[
+      ("AUTH0_AUDIENCES", " , "),
+      ("AUTH0_ISSUER", "https://example.auth0.com/"),
+    ])
+    .is_err());
+  }
+
+  #[test]
+  fn leeway_is_a_number_of_seconds() {
+    let config = |leeway| {
+      parse(&[
+        ("AUTH0_AUDIENCES", "https://example.com"),
+        ("AUTH0_ISSUER", "https://example.auth0.com/"),
+        ("AUTH0_LEEWAY", leeway),
+      ])
+    };
+
+    assert_eq!(
+      config("30")
+        .unwrap()
+        .leeway,
+      30
+    );
+    assert!(config("soon").is_err());
+    assert!(config("-1").is_err());
+  }
+}
@@ -3,7 +3,7 @@ use chrono::{DateTime, Utc};
 use futures::future::{err, ok, Ready};
 use uuid::Uuid;
 
-use jwt::dangerous_insecure_decode;
+use crate::middleware::auth::Claims;
 
 #[derive(Debug, Serialize)]
 pub struct User {
@@ -85,50 +85,23 @@ pub struct Auth0UserId {
   pub id: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Auth0UserClaims {
-  pub sub: String,
-}
-
 impl FromRequest for Auth0UserId {
   type Error = actix_web::Error;
-  // type Error = error::InternalError<String>;
   type Future = Ready<Result<Self, Self::Error>>;
   type Config = ();
 
+  // Relies on the validator middleware having already verified the token and stored its claims.
   fn from_request(req: &HttpRequest, _payload: &mut dev::Payload) -> Self::Future {
-    // let access_token_header_val = req.headers().get("authorization").unwrap(); // TODO: Get rid of this unwrap!
-    let access_token_header_val = req
-      .headers()
-      .get("authorization")
-      .ok_or_else(|| "Missing authorization header");
-
-    match access_token_header_val {
-      Ok(athv) => {
-        let access_token_str = athv
-          .to_str()
-          .unwrap();
-        let access_token: Vec<&str> = access_token_str
-          .split_whitespace()
-          .collect();
-
-        // Okay to do dangerous_unsafe_decode here because the user has already verified in middleware.
-        let decoded_token = dangerous_insecure_decode::<Auth0UserClaims>(
-          access_token
-            .get(1)
-            .unwrap(),
-        )
-        .ok()
-        .unwrap();
-
-        ok(Auth0UserId {
-          id: decoded_token
-            .claims
-            .sub
-            .clone(),
-        })
-      },
-      Err(e) => err(error::ErrorUnauthorized(e)),
+    match req
+      .extensions()
+      .get::<Claims>()
+    {
+      Some(claims) => ok(Auth0UserId {
+        id: claims
+          .sub
+          .clone(),
+      }),
+      None => err(error::ErrorUnauthorized("Missing verified access token")),
     }
   }
 }
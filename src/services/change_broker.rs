@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use sqlx::postgres::PgListener;
+use uuid::Uuid;
+
+use crate::graphql::schema::change::ChangeOperation;
+
+// Channel the notify_portal_change trigger publishes on.
+const CHANGES_CHANNEL: &str = "portal_changes";
+
+const RECONNECT_DELAY_SECS: u64 = 5;
+
+// What a subscriber is listening to. Cells, dimensions and portal views are scoped to a portal,
+// blocks to a portal view, matching how the frontend fetches them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Topic {
+  PortalCells(Uuid),
+  PortalViewBlocks(Uuid),
+  PortalDimensions(Uuid),
+  PortalViews(Uuid),
+}
+
+// Payload of a notification sent by the notify_portal_change trigger.
+#[derive(Debug, Deserialize)]
+struct ChangeNotification {
+  table: String,
+
+  op: String,
+
+  id: Uuid,
+
+  portal_id: Uuid,
+
+  portal_view_id: Option<Uuid>,
+}
+
+impl ChangeNotification {
+  fn topic(&self) -> Result<Topic> {
+    match self.table.as_str() {
+      "cells" => Ok(Topic::PortalCells(self.portal_id)),
+      "blocks" => self
+        .portal_view_id
+        .map(Topic::PortalViewBlocks)
+        .ok_or_else(|| anyhow!("Block change {} is missing its portal_view_id", self.id)),
+      "dimensions" => Ok(Topic::PortalDimensions(self.portal_id)),
+      "portalviews" => Ok(Topic::PortalViews(self.portal_id)),
+      table => Err(anyhow!(
+        "Unexpected change notification for table {}",
+        table
+      )),
+    }
+  }
+
+  fn operation(&self) -> Result<ChangeOperation> {
+    match self.op.as_str() {
+      "INSERT" => Ok(ChangeOperation::Created),
+      "UPDATE" => Ok(ChangeOperation::Updated),
+      "DELETE" => Ok(ChangeOperation::Deleted),
+      op => Err(anyhow!("Unexpected change operation {}", op)),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ChangeEvent {
+  pub operation: ChangeOperation,
+  pub id: Uuid,
+}
+
+impl ChangeEvent {
+  pub fn new(operation: ChangeOperation, id: Uuid) -> Self {
+    ChangeEvent { operation, id }
+  }
+}
+
+// In process fan out of change events to GraphQL subscriptions.
+#[derive(Default)]
+pub struct ChangeBroker {
+  subscribers: Mutex<HashMap<Topic, Vec<UnboundedSender<ChangeEvent>>>>,
+}
+
+impl ChangeBroker {
+  pub fn new() -> Self {
+    ChangeBroker::default()
+  }
+
+  pub fn subscribe(&self, topic: Topic) -> UnboundedReceiver<ChangeEvent> {
+    let (tx, rx) = unbounded();
+
+    self
+      .subscribers
+      .lock()
+      .expect("Change broker lock poisoned")
+      .entry(topic)
+      .or_insert_with(Vec::new)
+      .push(tx);
+
+    rx
+  }
+
+  // Feeds the broker from the database change feed, so writes made through any backend instance
+  // reach this instance's subscribers. Never returns, reconnecting whenever the listener fails.
+  pub async fn listen(&self, db_url: &str) {
+    loop {
+      if let Err(err) = self
+        .listen_until_error(db_url)
+        .await
+      {
+        println!("Change listener error, reconnecting: {:?}", err);
+      }
+
+      actix_web::rt::time::sleep(Duration::from_secs(RECONNECT_DELAY_SECS)).await;
+    }
+  }
+
+  async fn listen_until_error(&self, db_url: &str) -> Result<()> {
+    let mut listener = PgListener::connect(db_url).await?;
+
+    listener
+      .listen(CHANGES_CHANNEL)
+      .await?;
+
+    loop {
+      let notification = listener
+        .recv()
+        .await?;
+
+      match self.handle_notification(notification.payload()) {
+        Ok(_) => (),
+        Err(err) => println!("Unable to handle change notification: {:?}", err),
+      }
+    }
+  }
+
+  fn handle_notification(&self, payload: &str) -> Result<()> {
+    let notification = serde_json::from_str::<ChangeNotification>(payload)?;
+
+    self.publish(
+      notification.topic()?,
+      ChangeEvent::new(notification.operation()?, notification.id),
+    );
+
+    Ok(())
+  }
+
+  pub fn publish(&self, topic: Topic, event: ChangeEvent) {
+    let mut subscribers = self
+      .subscribers
+      .lock()
+      .expect("Change broker lock poisoned");
+
+    if let Some(senders) = subscribers.get_mut(&topic) {
+      // A failed send means the subscription's stream has been dropped, so forget about it.
+      senders.retain(|tx| {
+        tx.unbounded_send(event)
+          .is_ok()
+      });
+
+      if senders.is_empty() {
+        subscribers.remove(&topic);
+      }
+    }
+  }
+}
@@ -3,8 +3,17 @@ use super::DB;
 use anyhow::Result;
 use chrono::{DateTime, Utc};
 use serde_json;
+use sqlx::{Postgres, Transaction};
+use std::collections::HashSet;
+use std::str::FromStr;
 use uuid::Uuid;
 
+use crate::errors::ApiError;
+use crate::graphql::schema::block::{
+  block_data_from_value, BBox, BasicTableData, BlockTypes, ChartBlock, GQLBlocks, KeyValueBlock,
+};
+use crate::services::layout_service::{layout, LayoutConfig};
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DBBlock {
   pub id: Uuid,
@@ -50,19 +59,516 @@ pub struct DBNewBlock {
   pub bbox: Vec<i32>,
 
   pub data: serde_json::Value,
+}
 
-  #[serde(rename = "createdBy")]
-  pub created_by: Uuid,
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DBUpdateBlock {
+  pub id: Uuid,
 
-  #[serde(rename = "updatedBy")]
-  pub updated_by: Uuid,
+  pub egress: Option<String>,
+
+  pub bbox: Option<Vec<i32>>,
+
+  pub data: Option<serde_json::Value>,
+}
+
+fn validation_error(msg: String) -> anyhow::Error {
+  ApiError::Validation(msg).raise()
+}
+
+pub fn parse_bbox(bbox: &[i32]) -> Result<BBox> {
+  BBox::from_db(bbox).ok_or_else(|| {
+    validation_error(format!(
+      "bbox must have exactly 4 values, got {}",
+      bbox.len()
+    ))
+  })
+}
+
+// Writes the moved blocks of a portal view to their new positions, along with any other block the
+// layout pushes out of their way. The view's blocks are locked for the rest of the transaction, so
+// concurrent writes to the same view can't interleave and leave blocks overlapping.
+async fn layout_blocks(
+  tx: &mut Transaction<'static, Postgres>,
+  auth0id: &str,
+  portal_view_id: Uuid,
+  moved: &[(Uuid, BBox)],
+  config: &LayoutConfig,
+) -> Result<Vec<DBBlock>> {
+  let blocks = sqlx::query_as!(
+    DBBlock,
+    "select * from blocks where portal_view_id = $1 order by id for update",
+    portal_view_id
+  )
+  .fetch_all(&mut *tx)
+  .await?;
+
+  for (id, _) in moved {
+    if !blocks
+      .iter()
+      .any(|block| block.id == *id)
+    {
+      return Err(validation_error(format!(
+        "Block {} is not in portal view {}",
+        id, portal_view_id
+      )));
+    }
+  }
+
+  // Blocks with a malformed bbox don't take up any room, they get one once they're moved.
+  let others: Vec<(Uuid, BBox)> = blocks
+    .iter()
+    .filter(|block| {
+      !moved
+        .iter()
+        .any(|(id, _)| *id == block.id)
+    })
+    .filter_map(|block| BBox::from_db(&block.bbox).map(|bbox| (block.id, bbox)))
+    .collect();
+
+  let changed = layout(moved, &others, config).map_err(validation_error)?;
+
+  let mut updated = Vec::with_capacity(changed.len());
+
+  for (id, bbox) in changed {
+    let block = sqlx::query_as!(
+      DBBlock,
+      r#"
+      with _user as (select * from users where auth0id = $1)
+      update blocks
+        set
+          bbox = $3,
+          updated_by = (select id from _user)
+      where id = $2
+      returning *;
+      "#,
+      auth0id,
+      id,
+      &bbox.to_db()
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    updated.push(block);
+  }
+
+  Ok(updated)
+}
+
+fn take_block(blocks: Vec<DBBlock>, block_id: Uuid, fallback: DBBlock) -> DBBlock {
+  blocks
+    .into_iter()
+    .find(|block| block.id == block_id)
+    .unwrap_or(fallback)
 }
 
 impl DB {
-  pub async fn get_block(&self, block_id: Uuid) -> Result<DBBlock> {
-    sqlx::query_as!(DBBlock, "select * from blocks where id  = $1", block_id)
-      .fetch_one(&self.pool)
+  // Makes sure the jsonb that is about to be written matches the shape of the block type, so that
+  // reading it back out into the GraphQL union doesn't blow up, and that whatever the block
+  // references is in the block's portal.
+  pub async fn validate_block_data(
+    &self,
+    block_type: &str,
+    portal_id: Uuid,
+    data: &serde_json::Value,
+  ) -> Result<()> {
+    let block_type = BlockTypes::from_str(block_type)
+      .map_err(|_| validation_error(format!("Unknown block type: {}", block_type)))?;
+
+    let block_data = block_data_from_value(block_type, portal_id, data.clone()).map_err(|e| {
+      validation_error(format!(
+        "Invalid {} block data: {}",
+        block_type.to_string(),
+        e
+      ))
+    })?;
+
+    match block_data {
+      GQLBlocks::BasicTable(basic_table) => {
+        self
+          .validate_basic_table_block(portal_id, &basic_table.table)
+          .await
+      }
+      GQLBlocks::KeyValue(key_value) => {
+        self
+          .validate_key_value_block(portal_id, &key_value)
+          .await
+      }
+      GQLBlocks::Checklist(checklist) => {
+        if checklist
+          .items
+          .iter()
+          .any(|item| {
+            item
+              .text
+              .trim()
+              .is_empty()
+          })
+        {
+          return Err(validation_error(
+            "Checklist items must have text".to_string(),
+          ));
+        }
+
+        Ok(())
+      }
+      GQLBlocks::Chart(chart) => {
+        self
+          .validate_chart_block(portal_id, &chart)
+          .await
+      }
+      _ => Ok(()),
+    }
+  }
+
+  async fn validate_basic_table_block(
+    &self,
+    portal_id: Uuid,
+    table: &BasicTableData,
+  ) -> Result<()> {
+    let dimension_ids: Vec<Uuid> = table
+      .rows
+      .iter()
+      .chain(table.columns.iter())
+      .copied()
+      .collect();
+
+    self
+      .validate_portal_dimensions(portal_id, &dimension_ids)
       .await
-      .map_err(anyhow::Error::from)
+  }
+
+  async fn validate_key_value_block(
+    &self,
+    portal_id: Uuid,
+    key_value: &KeyValueBlock,
+  ) -> Result<()> {
+    let dimension_ids: Vec<Uuid> = key_value
+      .pairs
+      .iter()
+      .map(|pair| pair.dimension_id)
+      .collect();
+
+    let cell_ids: Vec<Uuid> = key_value
+      .pairs
+      .iter()
+      .filter_map(|pair| pair.cell_id)
+      .collect();
+
+    self
+      .validate_portal_dimensions(portal_id, &dimension_ids)
+      .await?;
+
+    let cells = self
+      .get_portal_cells(portal_id, &cell_ids)
+      .await?;
+
+    for cell_id in cell_ids {
+      if !cells
+        .iter()
+        .any(|cell| cell.id == cell_id)
+      {
+        return Err(validation_error(format!(
+          "Cell {} isn't in the block's portal",
+          cell_id
+        )));
+      }
+    }
+
+    Ok(())
+  }
+
+  async fn validate_portal_dimensions(
+    &self,
+    portal_id: Uuid,
+    dimension_ids: &[Uuid],
+  ) -> Result<()> {
+    let dimensions = self
+      .get_dimensions_by_ids(dimension_ids)
+      .await?;
+
+    for dimension_id in dimension_ids {
+      let in_portal = dimensions
+        .iter()
+        .any(|dimension| dimension.id == *dimension_id && dimension.portal_id == portal_id);
+
+      if !in_portal {
+        return Err(validation_error(format!(
+          "Dimension {} isn't in the block's portal",
+          dimension_id
+        )));
+      }
+    }
+
+    Ok(())
+  }
+
+  async fn validate_chart_block(&self, portal_id: Uuid, chart: &ChartBlock) -> Result<()> {
+    let table_block = sqlx::query_as!(
+      DBBlock,
+      "select * from blocks where id = $1 and portal_id = $2",
+      chart.table_block_id,
+      portal_id
+    )
+    .fetch_optional(&self.pool)
+    .await?
+    .ok_or_else(|| {
+      validation_error(format!(
+        "Block {} isn't in the chart's portal",
+        chart.table_block_id
+      ))
+    })?;
+
+    if table_block.block_type != BlockTypes::BasicTable.to_string() {
+      return Err(validation_error(format!(
+        "Charts can only plot BasicTable blocks, block {} is a {}",
+        table_block.id, table_block.block_type
+      )));
+    }
+
+    let table = serde_json::from_value::<BasicTableData>(table_block.data).map_err(|_| {
+      validation_error(format!(
+        "Block {} can't be read as a BasicTable",
+        table_block.id
+      ))
+    })?;
+
+    for series_id in chart
+      .spec
+      .series_ids
+      .iter()
+    {
+      if !table
+        .rows
+        .contains(series_id)
+      {
+        return Err(validation_error(format!(
+          "Series {} isn't a row of block {}",
+          series_id, table_block.id
+        )));
+      }
+    }
+
+    Ok(())
+  }
+
+  // Only returns the block if the user is an owner or vendor of the block's portal.
+  pub async fn get_auth0_user_block(&self, auth0_user_id: &str, block_id: Uuid) -> Result<DBBlock> {
+    sqlx::query_as!(
+      DBBlock,
+      r#"
+      with _user as (select * from users where auth0id = $1)
+      select blocks.* from blocks
+      inner join portals on portals.id = blocks.portal_id, _user
+      where blocks.id = $2 and
+      (_user.id = any(portals.owner_ids) or _user.id = any(portals.vendor_ids));
+      "#,
+      auth0_user_id,
+      block_id
+    )
+    .fetch_optional(&self.pool)
+    .await?
+    .ok_or_else(|| ApiError::NotFound(format!("Block {} not found", block_id)).raise())
+  }
+
+  pub async fn get_blocks(&self, portal_view_id: Uuid) -> Result<Vec<DBBlock>> {
+    sqlx::query_as!(
+      DBBlock,
+      "select * from blocks where portal_view_id = $1",
+      portal_view_id
+    )
+    .fetch_all(&self.pool)
+    .await
+    .map_err(anyhow::Error::from)
+  }
+
+  pub async fn create_block(
+    &self,
+    auth0id: &str,
+    new_block: DBNewBlock,
+    layout_config: &LayoutConfig,
+  ) -> Result<DBBlock> {
+    let portal_view = self
+      .get_auth0_user_portal_view(auth0id, new_block.portal_view_id)
+      .await?;
+
+    if portal_view.portal_id != new_block.portal_id {
+      return Err(validation_error(format!(
+        "Portal view {} is not in portal {}",
+        new_block.portal_view_id, new_block.portal_id
+      )));
+    }
+
+    self
+      .validate_block_data(&new_block.block_type, new_block.portal_id, &new_block.data)
+      .await?;
+    let bbox = parse_bbox(&new_block.bbox)?;
+
+    let mut tx = self
+      .pool
+      .begin()
+      .await?;
+
+    let block = sqlx::query_as!(
+      DBBlock,
+      r#"
+      with _user as (select * from users where auth0id = $1)
+      insert into blocks (block_type, portal_id, portal_view_id, egress, bbox, data, created_by, updated_by)
+      values ($2, $3, $4, $5, $6, $7, (select id from _user), (select id from _user))
+      returning *;
+      "#,
+      auth0id,
+      new_block.block_type,
+      new_block.portal_id,
+      new_block.portal_view_id,
+      new_block.egress,
+      &new_block.bbox,
+      new_block.data
+    )
+    .fetch_one(&mut tx)
+    .await?;
+
+    let laid_out = layout_blocks(
+      &mut tx,
+      auth0id,
+      block.portal_view_id,
+      &[(block.id, bbox)],
+      layout_config,
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(take_block(laid_out, block.id, block))
+  }
+
+  pub async fn update_block(
+    &self,
+    auth0id: &str,
+    update_block: DBUpdateBlock,
+    layout_config: &LayoutConfig,
+  ) -> Result<DBBlock> {
+    let block = self
+      .get_auth0_user_block(auth0id, update_block.id)
+      .await?;
+
+    if let Some(data) = &update_block.data {
+      // Block type can't be changed, so validate against the existing one.
+      self
+        .validate_block_data(&block.block_type, block.portal_id, data)
+        .await?;
+    }
+
+    let bbox = match &update_block.bbox {
+      Some(bbox) => Some(parse_bbox(bbox)?),
+      None => None,
+    };
+
+    let mut tx = self
+      .pool
+      .begin()
+      .await?;
+
+    let block = sqlx::query_as!(
+      DBBlock,
+      r#"
+      with _user as (select * from users where auth0id = $1)
+      update blocks
+        set
+          egress = coalesce($3, egress),
+          data = coalesce($4, data),
+          updated_by = (select id from _user)
+      where id = $2 and exists (
+        select 1 from portals
+        where portals.id = blocks.portal_id and
+        (select id from _user) = any(portals.owner_ids || portals.vendor_ids)
+      )
+      returning *;
+      "#,
+      auth0id,
+      block.id,
+      update_block.egress,
+      update_block.data
+    )
+    .fetch_optional(&mut tx)
+    .await?
+    .ok_or_else(|| ApiError::NotFound(format!("Block {} not found", block.id)).raise())?;
+
+    let block = match bbox {
+      Some(bbox) => {
+        let laid_out = layout_blocks(
+          &mut tx,
+          auth0id,
+          block.portal_view_id,
+          &[(block.id, bbox)],
+          layout_config,
+        )
+        .await?;
+
+        take_block(laid_out, block.id, block)
+      }
+      None => block,
+    };
+
+    tx.commit().await?;
+
+    Ok(block)
+  }
+
+  // Repositions several blocks of a portal view at once, either all of them move or none do.
+  // Returns every block whose position changed.
+  pub async fn move_blocks(
+    &self,
+    auth0id: &str,
+    portal_view_id: Uuid,
+    moves: Vec<(Uuid, BBox)>,
+    layout_config: &LayoutConfig,
+  ) -> Result<Vec<DBBlock>> {
+    let mut seen = HashSet::new();
+
+    for (id, _) in moves.iter() {
+      if !seen.insert(*id) {
+        return Err(validation_error(format!(
+          "Block {} is moved more than once",
+          id
+        )));
+      }
+    }
+
+    self
+      .get_auth0_user_portal_view(auth0id, portal_view_id)
+      .await?;
+
+    let mut tx = self
+      .pool
+      .begin()
+      .await?;
+
+    let blocks = layout_blocks(&mut tx, auth0id, portal_view_id, &moves, layout_config).await?;
+
+    tx.commit().await?;
+
+    Ok(blocks)
+  }
+
+  pub async fn delete_block(&self, auth0id: &str, block_id: Uuid) -> Result<DBBlock> {
+    sqlx::query_as!(
+      DBBlock,
+      r#"
+      with _user as (select * from users where auth0id = $1)
+      delete from blocks
+      where id = $2 and exists (
+        select 1 from portals
+        where portals.id = blocks.portal_id and
+        (select id from _user) = any(portals.owner_ids || portals.vendor_ids)
+      )
+      returning *;
+      "#,
+      auth0id,
+      block_id
+    )
+    .fetch_optional(&self.pool)
+    .await?
+    .ok_or_else(|| ApiError::NotFound(format!("Block {} not found", block_id)).raise())
   }
 }
@@ -3,8 +3,14 @@ use super::DB;
 use anyhow::Result;
 use chrono::{DateTime, Utc};
 use serde_json;
+use sqlx::{Postgres, Transaction};
+use std::str::FromStr;
 use uuid::Uuid;
 
+use crate::errors::ApiError;
+use crate::graphql::schema::cell::{cell_data_from_value, CellTypes, GQLCells};
+use crate::services::formula_service::parse;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DBCell {
   pub id: Uuid,
@@ -33,11 +39,398 @@ pub struct DBCell {
   pub updated_by: Uuid,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DBNewCell {
+  pub portal_id: Uuid,
+
+  pub cell_type: String,
+
+  pub dimensions: Vec<Uuid>,
+
+  pub data: serde_json::Value,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DBUpdateCell {
+  pub id: Uuid,
+
+  pub cell_type: Option<String>,
+
+  pub dimensions: Option<Vec<Uuid>>,
+
+  pub data: Option<serde_json::Value>,
+}
+
+pub const MAX_NUMBER_PRECISION: i32 = 10;
+
+fn validate_precision(precision: Option<i32>) -> Result<()> {
+  match precision {
+    Some(precision) if precision < 0 || precision > MAX_NUMBER_PRECISION => Err(
+      ApiError::Validation(format!(
+        "precision must be between 0 and {}, got {}",
+        MAX_NUMBER_PRECISION, precision
+      ))
+      .raise(),
+    ),
+    _ => Ok(()),
+  }
+}
+
+fn validate_choices<'a>(
+  options: &[String],
+  choices: impl Iterator<Item = &'a String>,
+) -> Result<()> {
+  for choice in choices {
+    if !options.contains(choice) {
+      return Err(
+        ApiError::Validation(format!(
+          "\"{}\" is not one of the options of the cell's dimensions",
+          choice
+        ))
+        .raise(),
+      );
+    }
+  }
+
+  Ok(())
+}
+
+// Cells may only sit on dimensions of their own portal.
+async fn check_cell_dimensions(
+  tx: &mut Transaction<'static, Postgres>,
+  portal_id: Uuid,
+  dimensions: &[Uuid],
+) -> Result<()> {
+  let mut ids = dimensions.to_vec();
+  ids.sort();
+  ids.dedup();
+
+  let found = sqlx::query!(
+    r#"select count(*) as "count!" from dimensions where id = any($1) and portal_id = $2"#,
+    &ids,
+    portal_id
+  )
+  .fetch_one(tx)
+  .await?
+  .count;
+
+  if found != ids.len() as i64 {
+    return Err(
+      ApiError::Validation(format!(
+        "Cell dimensions must belong to portal {}",
+        portal_id
+      ))
+      .raise(),
+    );
+  }
+
+  Ok(())
+}
+
 impl DB {
-  pub async fn get_cell(&self, cell_id: Uuid) -> Result<DBCell> {
-    sqlx::query_as!(DBCell, "select * from cells where id = $1", cell_id)
-      .fetch_one(&self.pool)
-      .await
-      .map_err(anyhow::Error::from)
+  // Makes sure the jsonb that is about to be written matches the shape of the cell type, that
+  // select choices are options of the cell's dimensions, and that formulas parse.
+  pub async fn validate_cell_data(
+    &self,
+    cell_type: &str,
+    dimension_ids: &[Uuid],
+    data: &serde_json::Value,
+  ) -> Result<()> {
+    let cell_type = CellTypes::from_str(cell_type)
+      .map_err(|_| ApiError::Validation(format!("Unknown cell type: {}", cell_type)).raise())?;
+
+    let cell_data = cell_data_from_value(cell_type, data.clone()).map_err(|e| {
+      ApiError::Validation(format!(
+        "Invalid {} cell data: {}",
+        cell_type.to_string(),
+        e
+      ))
+      .raise()
+    })?;
+
+    match cell_data {
+      GQLCells::Number(number) => validate_precision(number.precision),
+      GQLCells::SingleSelect(select) => {
+        let options = self
+          .get_select_options(dimension_ids)
+          .await?;
+
+        validate_choices(&options, select.choice.iter())
+      }
+      GQLCells::MultiSelect(select) => {
+        let options = self
+          .get_select_options(dimension_ids)
+          .await?;
+
+        validate_choices(
+          &options,
+          select
+            .choices
+            .iter(),
+        )
+      }
+      GQLCells::Formula(formula_cell) => parse(&formula_cell.formula)
+        .map(|_| ())
+        .map_err(|err| ApiError::Validation(err.to_string()).raise()),
+      _ => Ok(()),
+    }
   }
-}
\ No newline at end of file
+
+  async fn get_select_options(&self, dimension_ids: &[Uuid]) -> Result<Vec<String>> {
+    let dimensions = self
+      .get_dimensions_by_ids(dimension_ids)
+      .await?;
+
+    Ok(
+      dimensions
+        .iter()
+        .flat_map(|dimension| dimension.select_options())
+        .collect(),
+    )
+  }
+
+  // The cells of portals the user is an owner or vendor of, out of the given ones.
+  pub async fn get_auth0_user_cells_by_ids(
+    &self,
+    auth0_user_id: &str,
+    cell_ids: &[Uuid],
+  ) -> Result<Vec<DBCell>> {
+    sqlx::query_as!(
+      DBCell,
+      r#"
+      with _user as (select * from users where auth0id = $1)
+      select cells.* from cells
+      inner join portals on portals.id = cells.portal_id, _user
+      where cells.id = any($2) and
+      (_user.id = any(portals.owner_ids) or _user.id = any(portals.vendor_ids));
+      "#,
+      auth0_user_id,
+      cell_ids
+    )
+    .fetch_all(&self.pool)
+    .await
+    .map_err(anyhow::Error::from)
+  }
+
+  // Only returns the cell if the user is an owner or vendor of the cell's portal.
+  pub async fn get_auth0_user_cell(&self, auth0_user_id: &str, cell_id: Uuid) -> Result<DBCell> {
+    sqlx::query_as!(
+      DBCell,
+      r#"
+      with _user as (select * from users where auth0id = $1)
+      select cells.* from cells
+      inner join portals on portals.id = cells.portal_id, _user
+      where cells.id = $2 and
+      (_user.id = any(portals.owner_ids) or _user.id = any(portals.vendor_ids));
+      "#,
+      auth0_user_id,
+      cell_id
+    )
+    .fetch_optional(&self.pool)
+    .await?
+    .ok_or_else(|| ApiError::NotFound(format!("Cell {} not found", cell_id)).raise())
+  }
+
+  // Cells whose dimensions are all within the given dimensions.
+  pub async fn get_cells_by_dimensions(
+    &self,
+    portal_id: Uuid,
+    dimension_ids: &[Uuid],
+  ) -> Result<Vec<DBCell>> {
+    sqlx::query_as!(
+      DBCell,
+      "select * from cells where portal_id = $1 and dimensions <@ $2",
+      portal_id,
+      dimension_ids
+    )
+    .fetch_all(&self.pool)
+    .await
+    .map_err(anyhow::Error::from)
+  }
+
+  pub async fn get_portal_cells(&self, portal_id: Uuid, cell_ids: &[Uuid]) -> Result<Vec<DBCell>> {
+    sqlx::query_as!(
+      DBCell,
+      "select * from cells where portal_id = $1 and id = any($2)",
+      portal_id,
+      cell_ids
+    )
+    .fetch_all(&self.pool)
+    .await
+    .map_err(anyhow::Error::from)
+  }
+
+  // Cells within a portal that reference any of the given dimensions.
+  pub async fn get_portal_cells_by_dimensions(
+    &self,
+    portal_id: Uuid,
+    dimension_ids: &[Uuid],
+  ) -> Result<Vec<DBCell>> {
+    sqlx::query_as!(
+      DBCell,
+      "select * from cells where portal_id = $1 and dimensions && $2",
+      portal_id,
+      dimension_ids
+    )
+    .fetch_all(&self.pool)
+    .await
+    .map_err(anyhow::Error::from)
+  }
+
+  // Cells that reference one of the rows and one of the columns, ie. the cells of a BasicTable.
+  pub async fn get_cells_in_grid(
+    &self,
+    portal_id: Uuid,
+    row_ids: &[Uuid],
+    column_ids: &[Uuid],
+  ) -> Result<Vec<DBCell>> {
+    sqlx::query_as!(
+      DBCell,
+      "select * from cells where dimensions && $1 and dimensions && $2 and portal_id = $3",
+      row_ids,
+      column_ids,
+      portal_id
+    )
+    .fetch_all(&self.pool)
+    .await
+    .map_err(anyhow::Error::from)
+  }
+
+  // All or nothing, if one of the cells fails to insert none of them are created.
+  pub async fn create_cells(
+    &self,
+    auth0id: &str,
+    new_cells: Vec<DBNewCell>,
+  ) -> Result<Vec<DBCell>> {
+    let portal_ids: Vec<Uuid> = new_cells
+      .iter()
+      .map(|c| c.portal_id)
+      .collect();
+
+    self
+      .check_auth0_user_portals(auth0id, &portal_ids)
+      .await?;
+
+    for new_cell in new_cells.iter() {
+      self
+        .validate_cell_data(&new_cell.cell_type, &new_cell.dimensions, &new_cell.data)
+        .await?;
+    }
+
+    let mut tx = self
+      .pool
+      .begin()
+      .await?;
+
+    let mut cells = Vec::with_capacity(new_cells.len());
+
+    for new_cell in new_cells {
+      check_cell_dimensions(&mut tx, new_cell.portal_id, &new_cell.dimensions).await?;
+
+      let cell = sqlx::query_as!(
+        DBCell,
+        r#"
+        with _user as (select * from users where auth0id = $1)
+        insert into cells (portal_id, cell_type, dimensions, data, created_by, updated_by)
+        values ($2, $3, $4, $5, (select id from _user), (select id from _user))
+        returning *;
+        "#,
+        auth0id,
+        new_cell.portal_id,
+        new_cell.cell_type,
+        &new_cell.dimensions,
+        new_cell.data
+      )
+      .fetch_one(&mut tx)
+      .await?;
+
+      cells.push(cell);
+    }
+
+    tx.commit().await?;
+
+    Ok(cells)
+  }
+
+  pub async fn update_cell(&self, auth0id: &str, update_cell: DBUpdateCell) -> Result<DBCell> {
+    if update_cell
+      .cell_type
+      .is_some()
+      || update_cell
+        .dimensions
+        .is_some()
+      || update_cell
+        .data
+        .is_some()
+    {
+      let cell = self
+        .get_auth0_user_cell(auth0id, update_cell.id)
+        .await?;
+
+      let cell_type = update_cell
+        .cell_type
+        .as_ref()
+        .unwrap_or(&cell.cell_type);
+
+      let dimensions = update_cell
+        .dimensions
+        .as_ref()
+        .unwrap_or(&cell.dimensions);
+
+      let data = update_cell
+        .data
+        .as_ref()
+        .unwrap_or(&cell.data);
+
+      self
+        .validate_cell_data(cell_type, dimensions, data)
+        .await?;
+    }
+
+    let mut tx = self
+      .pool
+      .begin()
+      .await?;
+
+    if let Some(dimensions) = &update_cell.dimensions {
+      let cell = self
+        .get_auth0_user_cell(auth0id, update_cell.id)
+        .await?;
+
+      check_cell_dimensions(&mut tx, cell.portal_id, dimensions).await?;
+    }
+
+    let cell = sqlx::query_as!(
+      DBCell,
+      r#"
+      with _user as (select * from users where auth0id = $1)
+      update cells
+        set
+          cell_type = coalesce($3, cell_type),
+          dimensions = coalesce($4, dimensions),
+          data = coalesce($5, data),
+          updated_by = (select id from _user)
+      where id = $2 and exists (
+        select 1 from portals
+        where portals.id = cells.portal_id and
+        (select id from _user) = any(portals.owner_ids || portals.vendor_ids)
+      )
+      returning *;
+      "#,
+      auth0id,
+      update_cell.id,
+      update_cell.cell_type,
+      update_cell
+        .dimensions
+        .as_deref(),
+      update_cell.data
+    )
+    .fetch_optional(&mut tx)
+    .await?
+    .ok_or_else(|| ApiError::NotFound(format!("Cell {} not found", update_cell.id)).raise())?;
+
+    tx.commit().await?;
+
+    Ok(cell)
+  }
+}
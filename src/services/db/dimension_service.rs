@@ -1,3 +1,4 @@
+use super::pagination::{cursor_params, keyset_sql, DBCursor, DBPage};
 use super::DB;
 
 use anyhow::Result;
@@ -5,7 +6,11 @@ use chrono::{DateTime, Utc};
 
 use uuid::Uuid;
 
-#[derive(Debug, Serialize)]
+use crate::errors::ApiError;
+use crate::graphql::schema::dimension::DimensionFilter;
+use crate::graphql::schema::pagination::OrderBy;
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
 pub struct DBDimension {
   pub id: Uuid,
 
@@ -32,15 +37,408 @@ pub struct DBDimension {
   pub updated_by: Uuid,
 }
 
+impl DBDimension {
+  // Choices for SingleSelect and MultiSelect cells, kept in meta as { "options": ["..."] }.
+  pub fn select_options(&self) -> Vec<String> {
+    self
+      .meta
+      .get("options")
+      .and_then(|options| options.as_array())
+      .map(|options| {
+        options
+          .iter()
+          .filter_map(|option| option.as_str())
+          .map(String::from)
+          .collect()
+      })
+      .unwrap_or_default()
+  }
+}
+
+#[derive(Debug, Serialize)]
+pub struct DBNewDimension {
+  pub portal_id: Uuid,
+
+  pub name: String,
+
+  pub dimension_type: String,
+
+  pub meta: serde_json::Value,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DBUpdateDimension {
+  pub id: Uuid,
+
+  pub name: Option<String>,
+
+  pub meta: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct DBDimensionFilter {
+  pub dimension_type: Option<String>,
+
+  pub name_contains: Option<String>,
+}
+
+impl From<DimensionFilter> for DBDimensionFilter {
+  fn from(filter: DimensionFilter) -> Self {
+    DBDimensionFilter {
+      dimension_type: filter
+        .dimension_type
+        .map(|dt| dt.to_string()),
+      name_contains: filter.name_contains,
+    }
+  }
+}
+
 impl DB {
-  pub async fn get_dimensions(&self, portal_id: Uuid) -> Result<Vec<DBDimension>> {
+  // Only returns the dimension if the user is an owner or vendor of the dimension's portal.
+  pub async fn get_auth0_user_dimension(
+    &self,
+    auth0_user_id: &str,
+    dimension_id: Uuid,
+  ) -> Result<DBDimension> {
     sqlx::query_as!(
       DBDimension,
-      r#"select * from dimensions where portal_id = $1 "#,
-      portal_id
+      r#"
+      with _user as (select * from users where auth0id = $1)
+      select dimensions.* from dimensions
+      inner join portals on portals.id = dimensions.portal_id, _user
+      where dimensions.id = $2 and
+      (_user.id = any(portals.owner_ids) or _user.id = any(portals.vendor_ids));
+      "#,
+      auth0_user_id,
+      dimension_id
+    )
+    .fetch_optional(&self.pool)
+    .await?
+    .ok_or_else(|| ApiError::NotFound(format!("Dimension {} not found", dimension_id)).raise())
+  }
+
+  // The dimensions of portals the user is an owner or vendor of, out of the given ones.
+  pub async fn get_auth0_user_dimensions_by_ids(
+    &self,
+    auth0_user_id: &str,
+    dimension_ids: &[Uuid],
+  ) -> Result<Vec<DBDimension>> {
+    sqlx::query_as!(
+      DBDimension,
+      r#"
+      with _user as (select * from users where auth0id = $1)
+      select dimensions.* from dimensions
+      inner join portals on portals.id = dimensions.portal_id, _user
+      where dimensions.id = any($2) and
+      (_user.id = any(portals.owner_ids) or _user.id = any(portals.vendor_ids));
+      "#,
+      auth0_user_id,
+      dimension_ids
     )
     .fetch_all(&self.pool)
     .await
     .map_err(anyhow::Error::from)
   }
-}
\ No newline at end of file
+
+  pub async fn get_dimensions_by_ids(&self, dimension_ids: &[Uuid]) -> Result<Vec<DBDimension>> {
+    sqlx::query_as!(
+      DBDimension,
+      "select * from dimensions where id = any($1)",
+      dimension_ids
+    )
+    .fetch_all(&self.pool)
+    .await
+    .map_err(anyhow::Error::from)
+  }
+
+  // Built at runtime like get_auth0_user_portals, since the order by clause depends on order_by.
+  pub async fn get_dimensions(
+    &self,
+    portal_id: Uuid,
+    filter: DBDimensionFilter,
+    order_by: OrderBy,
+    first: i64,
+    after: Option<DBCursor>,
+  ) -> Result<DBPage<DBDimension>> {
+    let (after_created_at, after_id, after_name) = cursor_params(after);
+    let (keyset, order) = keyset_sql("dimensions", order_by, 4, 5, 6);
+
+    let query = format!(
+      r#"
+      select * from dimensions where portal_id = $1
+      and ($2::text is null or dimension_type = $2)
+      and ($3::text is null or strpos(lower(name), lower($3)) > 0)
+      and {}
+      order by {}
+      limit $7
+      "#,
+      keyset, order
+    );
+
+    let rows = sqlx::query_as::<_, DBDimension>(&query)
+      .bind(portal_id)
+      .bind(filter.dimension_type)
+      .bind(filter.name_contains)
+      .bind(after_created_at)
+      .bind(after_id)
+      .bind(after_name)
+      .bind(first + 1)
+      .fetch_all(&self.pool)
+      .await?;
+
+    Ok(DBPage::from_rows(rows, first))
+  }
+
+  pub async fn create_dimension(
+    &self,
+    auth0id: &str,
+    new_dimension: DBNewDimension,
+  ) -> Result<DBDimension> {
+    self
+      .get_auth0_user_portal(auth0id, new_dimension.portal_id)
+      .await?;
+
+    sqlx::query_as!(
+      DBDimension,
+      r#"
+      with _user as (select * from users where auth0id = $1)
+      insert into dimensions (portal_id, name, dimension_type, meta, created_by, updated_by)
+      values ($2, $3, $4, $5, (select id from _user), (select id from _user))
+      returning *;
+      "#,
+      auth0id,
+      new_dimension.portal_id,
+      new_dimension.name,
+      new_dimension.dimension_type,
+      new_dimension.meta
+    )
+    .fetch_one(&self.pool)
+    .await
+    .map_err(anyhow::Error::from)
+  }
+
+  // All or nothing, if one of the dimensions fails to insert none of them are created.
+  pub async fn create_dimensions(
+    &self,
+    auth0id: &str,
+    new_dimensions: Vec<DBNewDimension>,
+  ) -> Result<Vec<DBDimension>> {
+    let portal_ids: Vec<Uuid> = new_dimensions
+      .iter()
+      .map(|d| d.portal_id)
+      .collect();
+
+    self
+      .check_auth0_user_portals(auth0id, &portal_ids)
+      .await?;
+
+    let mut tx = self
+      .pool
+      .begin()
+      .await?;
+
+    let mut dimensions = Vec::with_capacity(new_dimensions.len());
+
+    for new_dimension in new_dimensions {
+      let dimension = sqlx::query_as!(
+        DBDimension,
+        r#"
+        with _user as (select * from users where auth0id = $1)
+        insert into dimensions (portal_id, name, dimension_type, meta, created_by, updated_by)
+        values ($2, $3, $4, $5, (select id from _user), (select id from _user))
+        returning *;
+        "#,
+        auth0id,
+        new_dimension.portal_id,
+        new_dimension.name,
+        new_dimension.dimension_type,
+        new_dimension.meta
+      )
+      .fetch_one(&mut tx)
+      .await?;
+
+      dimensions.push(dimension);
+    }
+
+    tx.commit().await?;
+
+    Ok(dimensions)
+  }
+
+  pub async fn update_dimension(
+    &self,
+    auth0id: &str,
+    update_dimension: DBUpdateDimension,
+  ) -> Result<DBDimension> {
+    sqlx::query_as!(
+      DBDimension,
+      r#"
+      with _user as (select * from users where auth0id = $1)
+      update dimensions
+        set
+          name = coalesce($3, name),
+          meta = coalesce($4, meta),
+          updated_by = (select id from _user)
+      where id = $2 and exists (
+        select 1 from portals
+        where portals.id = dimensions.portal_id and
+        (select id from _user) = any(portals.owner_ids || portals.vendor_ids)
+      )
+      returning *;
+      "#,
+      auth0id,
+      update_dimension.id,
+      update_dimension.name,
+      update_dimension.meta
+    )
+    .fetch_optional(&self.pool)
+    .await?
+    .ok_or_else(|| {
+      ApiError::NotFound(format!("Dimension {} not found", update_dimension.id)).raise()
+    })
+  }
+
+  // Refuses to delete a dimension that cells still live in, otherwise those cells would be
+  // orphaned. Any BasicTable blocks using the dimension as a row or column are cleaned up.
+  pub async fn delete_dimension(&self, auth0id: &str, dimension_id: Uuid) -> Result<DBDimension> {
+    let mut tx = self
+      .pool
+      .begin()
+      .await?;
+
+    sqlx::query!(
+      r#"
+      with _user as (select * from users where auth0id = $1)
+      select dimensions.id from dimensions
+      inner join portals on portals.id = dimensions.portal_id
+      where dimensions.id = $2 and
+      (select id from _user) = any(portals.owner_ids || portals.vendor_ids)
+      for update of dimensions;
+      "#,
+      auth0id,
+      dimension_id
+    )
+    .fetch_optional(&mut tx)
+    .await?
+    .ok_or_else(|| ApiError::NotFound(format!("Dimension {} not found", dimension_id)).raise())?;
+
+    let cell_count = sqlx::query!(
+      "select count(*) as cell_count from cells where $1 = any(dimensions)",
+      dimension_id
+    )
+    .fetch_one(&mut tx)
+    .await?
+    .cell_count
+    .unwrap_or(0);
+
+    if cell_count > 0 {
+      return Err(
+        ApiError::Conflict(format!(
+          "Unable to delete dimension {}, it is still used by {} cell(s)",
+          dimension_id, cell_count
+        ))
+        .raise(),
+      );
+    }
+
+    let dimension = sqlx::query_as!(
+      DBDimension,
+      "delete from dimensions where id = $1 returning *",
+      dimension_id
+    )
+    .fetch_one(&mut tx)
+    .await?;
+
+    sqlx::query!(
+      r#"
+      update blocks
+        set data = jsonb_set(
+          jsonb_set(
+            data,
+            '{rows}',
+            coalesce(
+              (
+                select jsonb_agg(r order by i)
+                from jsonb_array_elements(data->'rows') with ordinality as _rows(r, i)
+                where r <> to_jsonb($1::uuid::text)
+              ),
+              '[]'::jsonb
+            )
+          ),
+          '{columns}',
+          coalesce(
+            (
+              select jsonb_agg(c order by i)
+              from jsonb_array_elements(data->'columns') with ordinality as _columns(c, i)
+              where c <> to_jsonb($1::uuid::text)
+            ),
+            '[]'::jsonb
+          )
+        )
+      where portal_id = $2
+        and block_type = 'BasicTable'
+        and (data->'rows' ? $1::uuid::text or data->'columns' ? $1::uuid::text);
+      "#,
+      dimension.id,
+      dimension.portal_id
+    )
+    .execute(&mut tx)
+    .await?;
+
+    // Key-value pairs keyed by the dimension go with it.
+    sqlx::query!(
+      r#"
+      update blocks
+        set data = jsonb_set(
+          data,
+          '{pairs}',
+          coalesce(
+            (
+              select jsonb_agg(p order by i)
+              from jsonb_array_elements(data->'pairs') with ordinality as _pairs(p, i)
+              where p->>'dimensionId' <> $1::uuid::text
+            ),
+            '[]'::jsonb
+          )
+        )
+      where portal_id = $2
+        and block_type = 'KeyValue'
+        and data->'pairs' @> jsonb_build_array(jsonb_build_object('dimensionId', $1::uuid::text));
+      "#,
+      dimension.id,
+      dimension.portal_id
+    )
+    .execute(&mut tx)
+    .await?;
+
+    // And so do chart series plotting it, now that it's no longer a row of their table.
+    sqlx::query!(
+      r#"
+      update blocks
+        set data = jsonb_set(
+          data,
+          '{spec,seriesIds}',
+          coalesce(
+            (
+              select jsonb_agg(s order by i)
+              from jsonb_array_elements(data->'spec'->'seriesIds') with ordinality as _series(s, i)
+              where s <> to_jsonb($1::uuid::text)
+            ),
+            '[]'::jsonb
+          )
+        )
+      where portal_id = $2
+        and block_type = 'Chart'
+        and data->'spec'->'seriesIds' ? $1::uuid::text;
+      "#,
+      dimension.id,
+      dimension.portal_id
+    )
+    .execute(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(dimension)
+  }
+}
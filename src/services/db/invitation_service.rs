@@ -0,0 +1,223 @@
+use super::DB;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::errors::ApiError;
+use crate::services::auth0_service::Auth0User;
+use crate::services::db::user_service::DBUser;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DBInvitation {
+  pub id: Uuid,
+
+  #[serde(rename = "userId")]
+  pub user_id: Uuid,
+
+  #[serde(rename = "portalId")]
+  pub portal_id: Uuid,
+
+  pub egress: String,
+
+  #[serde(rename = "expiresAt")]
+  pub expires_at: DateTime<Utc>,
+
+  #[serde(rename = "acceptedAt")]
+  pub accepted_at: Option<DateTime<Utc>>,
+
+  #[serde(rename = "acceptedBy")]
+  pub accepted_by: Option<Uuid>,
+
+  #[serde(rename = "createdAt")]
+  pub created_at: DateTime<Utc>,
+
+  #[serde(rename = "createdBy")]
+  pub created_by: Uuid,
+
+  #[serde(rename = "updatedAt")]
+  pub updated_at: DateTime<Utc>,
+
+  #[serde(rename = "updatedBy")]
+  pub updated_by: Uuid,
+}
+
+// Ids of both, in order, without duplicates.
+fn merge_ids(ids: &[Uuid], other_ids: &[Uuid]) -> Vec<Uuid> {
+  let mut merged = ids.to_vec();
+
+  for id in other_ids {
+    if !merged.contains(id) {
+      merged.push(*id);
+    }
+  }
+
+  merged
+}
+
+impl DB {
+  // Binds the requesting Auth0 identity to the invited user of the invitation. When the requesting
+  // user already exists, the invited user's portals, orgs and roles are merged into theirs and the
+  // invited user is removed instead. `profile` fills in the invited user's name and nickname.
+  pub async fn accept_invitation(
+    &self,
+    auth0id: &str,
+    invitation_id: Uuid,
+    profile: Option<&Auth0User>,
+  ) -> Result<DBUser> {
+    let mut tx = self
+      .pool
+      .begin()
+      .await?;
+
+    let invitation = sqlx::query_as!(
+      DBInvitation,
+      "select * from invitations where id = $1 for update",
+      invitation_id
+    )
+    .fetch_optional(&mut tx)
+    .await?
+    .ok_or_else(|| ApiError::NotFound(format!("Invitation {} not found", invitation_id)).raise())?;
+
+    if invitation
+      .accepted_at
+      .is_some()
+    {
+      return Err(ApiError::Conflict(String::from("Invitation has already been used")).raise());
+    }
+
+    if invitation.expires_at < Utc::now() {
+      return Err(ApiError::Validation(String::from("Invitation has expired")).raise());
+    }
+
+    let invited_user = sqlx::query_as!(
+      DBUser,
+      "select * from users where id = $1 for update",
+      invitation.user_id
+    )
+    .fetch_one(&mut tx)
+    .await?;
+
+    let requesting_user = sqlx::query_as!(
+      DBUser,
+      "select * from users where auth0id = $1 for update",
+      auth0id
+    )
+    .fetch_optional(&mut tx)
+    .await?;
+
+    let user = match requesting_user {
+      Some(user) if user.id == invited_user.id => user,
+      Some(user) => {
+        if invited_user.status != "invited" {
+          return Err(
+            ApiError::Conflict(String::from("Invitation belongs to another user")).raise(),
+          );
+        }
+
+        sqlx::query!(
+          r#"
+          update portals
+            set
+              owner_ids = case
+                when $2 = any(owner_ids) then array_remove(owner_ids, $1)
+                else array_replace(owner_ids, $1, $2)
+              end,
+              vendor_ids = case
+                when $2 = any(vendor_ids) then array_remove(vendor_ids, $1)
+                else array_replace(vendor_ids, $1, $2)
+              end,
+              updated_by = $2
+          where $1 = any(owner_ids) or $1 = any(vendor_ids);
+          "#,
+          invited_user.id,
+          user.id
+        )
+        .execute(&mut tx)
+        .await?;
+
+        sqlx::query!(
+          "update invitations set user_id = $2, updated_by = $2 where user_id = $1",
+          invited_user.id,
+          user.id
+        )
+        .execute(&mut tx)
+        .await?;
+
+        let user = sqlx::query_as!(
+          DBUser,
+          r#"
+          update users
+            set
+              org_ids = $2,
+              role_ids = $3,
+              updated_by = $1
+          where id = $1
+          returning *;
+          "#,
+          user.id,
+          &merge_ids(&user.org_ids, &invited_user.org_ids),
+          &merge_ids(&user.role_ids, &invited_user.role_ids)
+        )
+        .fetch_one(&mut tx)
+        .await?;
+
+        sqlx::query!("delete from users where id = $1", invited_user.id)
+          .execute(&mut tx)
+          .await?;
+
+        user
+      }
+      None => {
+        if !invited_user
+          .auth0id
+          .is_empty()
+        {
+          return Err(
+            ApiError::Conflict(String::from("Invitation belongs to another user")).raise(),
+          );
+        }
+
+        sqlx::query_as!(
+          DBUser,
+          r#"
+          update users
+            set
+              auth0id = $2,
+              status = 'active',
+              name = case when name = '' then coalesce($3, name) else name end,
+              nickname = case when nickname = '' then coalesce($4, nickname) else nickname end,
+              updated_by = $1
+          where id = $1
+          returning *;
+          "#,
+          invited_user.id,
+          auth0id,
+          profile.map(|p| p.name.clone()),
+          profile.map(|p| p.nickname.clone())
+        )
+        .fetch_one(&mut tx)
+        .await?
+      }
+    };
+
+    sqlx::query!(
+      r#"
+      update invitations
+        set
+          accepted_at = now(),
+          accepted_by = $2,
+          updated_by = $2
+      where id = $1;
+      "#,
+      invitation.id,
+      user.id
+    )
+    .execute(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(user)
+  }
+}
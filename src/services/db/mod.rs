@@ -7,5 +7,7 @@ pub mod portalview_service;
 pub mod block_service;
 pub mod dimension_service;
 pub mod cell_service;
+pub mod invitation_service;
+pub mod pagination;
 
 pub use db::*;
\ No newline at end of file
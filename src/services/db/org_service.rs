@@ -1,9 +1,12 @@
+use super::pagination::{cursor_params, DBCursor, DBPage};
 use super::DB;
 // use crate::models::db_org::{DBOrg, NewDBOrg};
 use anyhow::Result;
 use chrono::{DateTime, NaiveDateTime, Utc};
 use uuid::Uuid;
 
+use crate::errors::ApiError;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DBOrg {
   pub id: Uuid,
@@ -42,7 +45,7 @@ pub struct DBNewOrg {
 }
 
 impl DB {
-  // Needs to be scoped to the user's orgs...
+  // Not scoped to the user's orgs, use get_auth0_user_org for anything user facing.
   pub async fn get_org(&self, org_id: Uuid) -> Result<DBOrg> {
     sqlx::query_as!(DBOrg, "select * from orgs where id = $1", org_id)
       .fetch_one(&self.pool)
@@ -50,6 +53,54 @@ impl DB {
       .map_err(anyhow::Error::from)
   }
 
+  // Only returns the org if it is one of the user's orgs. An org the user isn't a member of
+  // is reported as not found, so that whether or not it exists isn't leaked.
+  pub async fn get_auth0_user_org(&self, auth0_user_id: &str, org_id: Uuid) -> Result<DBOrg> {
+    sqlx::query_as!(
+      DBOrg,
+      r#"
+      with _user as (select * from users where auth0id = $1)
+      select orgs.* from orgs, _user
+      where orgs.id = $2 and orgs.id = any(_user.org_ids);
+      "#,
+      auth0_user_id,
+      org_id
+    )
+    .fetch_optional(&self.pool)
+    .await?
+    .ok_or_else(|| ApiError::NotFound(format!("Org {} not found", org_id)).raise())
+  }
+
+  // The user's orgs, oldest first.
+  pub async fn get_auth0_user_orgs(
+    &self,
+    auth0_user_id: &str,
+    first: i64,
+    after: Option<DBCursor>,
+  ) -> Result<DBPage<DBOrg>> {
+    let (after_created_at, after_id, _) = cursor_params(after);
+
+    let rows = sqlx::query_as!(
+      DBOrg,
+      r#"
+      with _user as (select * from users where auth0id = $1)
+      select orgs.* from orgs, _user
+      where orgs.id = any(_user.org_ids)
+      and ($2::timestamptz is null or (orgs.created_at, orgs.id) > ($2, $3::uuid))
+      order by orgs.created_at, orgs.id
+      limit $4;
+      "#,
+      auth0_user_id,
+      after_created_at,
+      after_id,
+      first + 1
+    )
+    .fetch_all(&self.pool)
+    .await?;
+
+    Ok(DBPage::from_rows(rows, first))
+  }
+
   // Note: This is realllllllly bad, and will return all orgs in the db.
   //       Should probably take an array of org ids.
   pub async fn get_orgs(&self, ids: &[Uuid]) -> Result<Vec<DBOrg>> {
@@ -0,0 +1,143 @@
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::graphql::schema::pagination::OrderBy;
+
+// Position of a row in a list ordered by (created_at, id) or (name, id). Rows after the cursor are
+// strictly greater than it, so pages stay stable when rows are inserted while paginating. The
+// cursor carries both sort keys, so it stays valid when its row is renamed or deleted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DBCursor {
+  pub created_at: DateTime<Utc>,
+
+  pub id: Uuid,
+
+  pub name: String,
+}
+
+#[derive(Debug)]
+pub struct DBPage<T> {
+  pub items: Vec<T>,
+
+  pub has_next_page: bool,
+}
+
+impl<T> DBPage<T> {
+  // Queries fetch one more row than was asked for to find out if there is another page.
+  pub fn from_rows(mut rows: Vec<T>, first: i64) -> Self {
+    let has_next_page = rows.len() as i64 > first;
+
+    rows.truncate(first as usize);
+
+    DBPage {
+      items: rows,
+      has_next_page,
+    }
+  }
+}
+
+// Splits an optional cursor into the nullable parameters the keyset queries take.
+pub fn cursor_params(
+  after: Option<DBCursor>,
+) -> (Option<DateTime<Utc>>, Option<Uuid>, Option<String>) {
+  match after {
+    Some(cursor) => (Some(cursor.created_at), Some(cursor.id), Some(cursor.name)),
+    None => (None, None, None),
+  }
+}
+
+// The keyset condition and order by clause for listing rows of `table` in the given order, for
+// queries built at runtime. The cursor's created_at, id and name must be bound to the
+// `created_at_param`, `id_param` and `name_param` positional parameters.
+pub fn keyset_sql(
+  table: &str,
+  order_by: OrderBy,
+  created_at_param: usize,
+  id_param: usize,
+  name_param: usize,
+) -> (String, String) {
+  let (column, cmp, direction) = match order_by {
+    OrderBy::CreatedAtAsc => ("created_at", ">", "asc"),
+    OrderBy::CreatedAtDesc => ("created_at", "<", "desc"),
+    OrderBy::NameAsc => ("name", ">", "asc"),
+    OrderBy::NameDesc => ("name", "<", "desc"),
+  };
+
+  let after = match order_by {
+    OrderBy::CreatedAtAsc | OrderBy::CreatedAtDesc => {
+      format!("(${}::timestamptz, ${}::uuid)", created_at_param, id_param)
+    }
+    OrderBy::NameAsc | OrderBy::NameDesc => {
+      format!("(${}::text, ${}::uuid)", name_param, id_param)
+    }
+  };
+
+  let keyset = format!(
+    "(${}::timestamptz is null or ({}.{}, {}.id) {} {})",
+    created_at_param, table, column, table, cmp, after
+  );
+
+  let order = format!(
+    "{}.{} {}, {}.id {}",
+    table, column, direction, table, direction
+  );
+
+  (keyset, order)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn pages_know_if_there_is_another_page() {
+    let page = DBPage::from_rows(vec![1, 2, 3], 2);
+
+    assert_eq!(page.items, vec![1, 2]);
+    assert!(page.has_next_page);
+
+    let page = DBPage::from_rows(vec![1, 2], 2);
+
+    assert_eq!(page.items, vec![1, 2]);
+    assert!(!page.has_next_page);
+  }
+
+  #[test]
+  fn cursor_params_are_null_without_a_cursor() {
+    assert_eq!(cursor_params(None), (None, None, None));
+
+    let cursor = DBCursor {
+      created_at: Utc::now(),
+      id: Uuid::new_v4(),
+      name: String::from("Budget"),
+    };
+
+    assert_eq!(
+      cursor_params(Some(cursor.clone())),
+      (Some(cursor.created_at), Some(cursor.id), Some(cursor.name))
+    );
+  }
+
+  #[test]
+  fn keyset_by_created_at() {
+    let (keyset, order) = keyset_sql("portals", OrderBy::CreatedAtDesc, 5, 6, 7);
+
+    assert_eq!(
+      keyset,
+      "($5::timestamptz is null or (portals.created_at, portals.id) < ($5::timestamptz, $6::uuid))"
+    );
+    assert_eq!(order, "portals.created_at desc, portals.id desc");
+  }
+
+  // The name is compared against the cursor's own, so paging keeps working after its row is gone.
+  #[test]
+  fn keyset_by_name() {
+    let (keyset, order) = keyset_sql("dimensions", OrderBy::NameAsc, 4, 5, 6);
+
+    assert_eq!(
+      keyset,
+      "($4::timestamptz is null or (dimensions.name, dimensions.id) > ($6::text, $5::uuid))"
+    );
+    assert_eq!(order, "dimensions.name asc, dimensions.id asc");
+  }
+}
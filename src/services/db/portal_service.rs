@@ -1,10 +1,21 @@
+use super::pagination::{cursor_params, keyset_sql, DBCursor, DBPage};
 use super::DB;
 
 use anyhow::Result;
 use chrono::{DateTime, Utc};
+use serde_json::json;
+use sqlx::{Postgres, Transaction};
 use uuid::Uuid;
 
-#[derive(Debug, Serialize, Deserialize)]
+use crate::errors::ApiError;
+use crate::graphql::authorization::Permission;
+use crate::graphql::schema::pagination::OrderBy;
+use crate::graphql::schema::portal::{NewPortal, PortalFilter, UpdatePortal};
+use crate::services::db::invitation_service::DBInvitation;
+use crate::services::db::role_service::DBRole;
+use crate::services::db::user_service::DBUser;
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
 pub struct DBPortal {
   pub id: Uuid,
 
@@ -29,23 +40,157 @@ pub struct DBPortal {
   pub updated_by: Uuid,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct DBNewPortal {
   pub org: Uuid,
 
   pub name: String,
 
-  #[serde(rename = "createdBy")]
-  pub created_by: Uuid,
-
-  #[serde(rename = "updatedBy")]
-  pub updated_by: Uuid,
-
   pub owner_ids: Vec<Uuid>,
 
   pub vendor_ids: Vec<Uuid>,
 }
 
+impl From<NewPortal> for DBNewPortal {
+  fn from(new_portal: NewPortal) -> Self {
+    DBNewPortal {
+      org: new_portal.org,
+      name: new_portal.name,
+      owner_ids: new_portal
+        .owner_ids
+        .unwrap_or_else(|| vec![]),
+      vendor_ids: new_portal
+        .vendor_ids
+        .unwrap_or_else(|| vec![]),
+    }
+  }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DBUpdatePortal {
+  pub id: Uuid,
+
+  pub name: Option<String>,
+
+  pub owner_ids: Option<Vec<Uuid>>,
+
+  pub vendor_ids: Option<Vec<Uuid>>,
+}
+
+impl From<UpdatePortal> for DBUpdatePortal {
+  fn from(update_portal: UpdatePortal) -> Self {
+    DBUpdatePortal {
+      id: update_portal.id,
+      name: update_portal.name,
+      owner_ids: update_portal.owner_ids,
+      vendor_ids: update_portal.vendor_ids,
+    }
+  }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct DBPortalFilter {
+  pub org_id: Option<Uuid>,
+
+  // "owner" or "vendor", which side of the portal the requesting user is on.
+  pub egress: Option<String>,
+
+  pub name_contains: Option<String>,
+}
+
+impl From<PortalFilter> for DBPortalFilter {
+  fn from(filter: PortalFilter) -> Self {
+    DBPortalFilter {
+      org_id: filter.org_id,
+      egress: filter
+        .egress
+        .map(|e| e.to_string()),
+      name_contains: filter.name_contains,
+    }
+  }
+}
+
+// Writes to a portal, by what its members may do. Vendors can rename the portal and invite other
+// vendors, who its members are and whether it exists at all is up to its owners.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PortalWrite {
+  Rename,
+  EditMembers,
+  Delete,
+  InviteOwner,
+  InviteVendor,
+}
+
+impl PortalWrite {
+  pub fn of_update(update_portal: &DBUpdatePortal) -> Self {
+    if update_portal
+      .owner_ids
+      .is_some()
+      || update_portal
+        .vendor_ids
+        .is_some()
+    {
+      PortalWrite::EditMembers
+    } else {
+      PortalWrite::Rename
+    }
+  }
+}
+
+// Portals the user isn't a member of are reported as not found, like everywhere else.
+pub fn authorize_portal_write(portal: &DBPortal, user_id: Uuid, write: PortalWrite) -> Result<()> {
+  if portal
+    .owner_ids
+    .contains(&user_id)
+  {
+    return Ok(());
+  }
+
+  if !portal
+    .vendor_ids
+    .contains(&user_id)
+  {
+    return Err(ApiError::NotFound(format!("Portal {} not found", portal.id)).raise());
+  }
+
+  match write {
+    PortalWrite::Rename | PortalWrite::InviteVendor => Ok(()),
+    PortalWrite::EditMembers | PortalWrite::Delete | PortalWrite::InviteOwner => {
+      Err(ApiError::Forbidden(Permission::EditPortal).raise())
+    }
+  }
+}
+
+// Perms of the role users invited to a portal get. Both sides can view the portal, owners can edit
+// it as well.
+pub fn invitee_perms(egress: &str) -> serde_json::Value {
+  json!({
+    "view_portal": true,
+    "edit_portal": egress == "owner",
+  })
+}
+
+// Locks the portal for the rest of the transaction, once the user is allowed the write.
+async fn lock_portal_for_write(
+  tx: &mut Transaction<'static, Postgres>,
+  user_id: Uuid,
+  portal_id: Uuid,
+  write: PortalWrite,
+) -> Result<DBPortal> {
+  let portal = sqlx::query_as!(
+    DBPortal,
+    "select * from portals where id = $1 for update",
+    portal_id
+  )
+  .fetch_optional(tx)
+  .await?
+  .ok_or_else(|| ApiError::NotFound(format!("Portal {} not found", portal_id)).raise())?;
+
+  authorize_portal_write(&portal, user_id, write)?;
+
+  Ok(portal)
+}
+
 impl DB {
   pub async fn get_portal(&self, portal_id: Uuid) -> Result<DBPortal> {
     sqlx::query_as!(DBPortal, "select * from portals where id = $1", portal_id)
@@ -54,30 +199,559 @@ impl DB {
       .map_err(anyhow::Error::from)
   }
 
-  pub async fn get_portals(&self, portal_ids: Vec<Uuid>) -> Result<Vec<DBPortal>> {
+  // Only returns the portal if the user is one of its owners or vendors. A portal the user
+  // isn't a member of is reported as not found, so that whether or not it exists isn't leaked.
+  pub async fn get_auth0_user_portal(
+    &self,
+    auth0_user_id: &str,
+    portal_id: Uuid,
+  ) -> Result<DBPortal> {
     sqlx::query_as!(
       DBPortal,
-      "select * from portals where id = any($1)",
-      &portal_ids
+      r#"
+      with _user as (select * from users where auth0id = $1)
+      select portals.* from portals, _user
+      where portals.id = $2 and
+      (_user.id = any(portals.owner_ids) or _user.id = any(portals.vendor_ids));
+      "#,
+      auth0_user_id,
+      portal_id
     )
-    .fetch_all(&self.pool)
-    .await
-    .map_err(anyhow::Error::from)
+    .fetch_optional(&self.pool)
+    .await?
+    .ok_or_else(|| ApiError::NotFound(format!("Portal {} not found", portal_id)).raise())
+  }
+
+  // Fails like get_auth0_user_portal unless the user is a member of every one of the portals.
+  pub async fn check_auth0_user_portals(
+    &self,
+    auth0_user_id: &str,
+    portal_ids: &[Uuid],
+  ) -> Result<()> {
+    let mut portal_ids = portal_ids.to_vec();
+    portal_ids.sort();
+    portal_ids.dedup();
+
+    for portal_id in portal_ids {
+      self
+        .get_auth0_user_portal(auth0_user_id, portal_id)
+        .await?;
+    }
+
+    Ok(())
   }
 
-  pub async fn get_auth0_user_portals(&self, auth0_user_id: &str) -> Result<Vec<DBPortal>> {
+  // The portals the user is an owner or vendor of, out of the given ones.
+  pub async fn get_auth0_user_portals_by_ids(
+    &self,
+    auth0_user_id: &str,
+    portal_ids: &[Uuid],
+  ) -> Result<Vec<DBPortal>> {
     sqlx::query_as!(
       DBPortal,
       r#"
       with _user as (select * from users where auth0id = $1)
-      select * from portals where
-      (select id from _user) = any(owner_ids) or
-      (select id from _user) = any(vendor_ids);
+      select portals.* from portals, _user
+      where portals.id = any($2) and
+      (_user.id = any(portals.owner_ids) or _user.id = any(portals.vendor_ids));
       "#,
-      auth0_user_id
+      auth0_user_id,
+      portal_ids
     )
     .fetch_all(&self.pool)
     .await
     .map_err(anyhow::Error::from)
   }
+
+  // Portals the user is an owner or vendor of. The query is put together at runtime since the
+  // order by clause depends on order_by, every value is still passed as a parameter.
+  pub async fn get_auth0_user_portals(
+    &self,
+    auth0_user_id: &str,
+    filter: DBPortalFilter,
+    order_by: OrderBy,
+    first: i64,
+    after: Option<DBCursor>,
+  ) -> Result<DBPage<DBPortal>> {
+    let (after_created_at, after_id, after_name) = cursor_params(after);
+    let (keyset, order) = keyset_sql("portals", order_by, 5, 6, 7);
+
+    let query = format!(
+      r#"
+      with _user as (select * from users where auth0id = $1)
+      select portals.* from portals, _user
+      where (
+        (($2::text is null or $2 = 'owner') and _user.id = any(portals.owner_ids)) or
+        (($2::text is null or $2 = 'vendor') and _user.id = any(portals.vendor_ids))
+      )
+      and ($3::uuid is null or portals.org = $3)
+      and ($4::text is null or strpos(lower(portals.name), lower($4)) > 0)
+      and {}
+      order by {}
+      limit $8;
+      "#,
+      keyset, order
+    );
+
+    let rows = sqlx::query_as::<_, DBPortal>(&query)
+      .bind(auth0_user_id)
+      .bind(filter.egress)
+      .bind(filter.org_id)
+      .bind(filter.name_contains)
+      .bind(after_created_at)
+      .bind(after_id)
+      .bind(after_name)
+      .bind(first + 1)
+      .fetch_all(&self.pool)
+      .await?;
+
+    Ok(DBPage::from_rows(rows, first))
+  }
+
+  // Creates the portal along with the default "owner" and "vendor" portal views.
+  pub async fn create_portal(&self, auth0id: &str, new_portal: DBNewPortal) -> Result<DBPortal> {
+    let mut tx = self
+      .pool
+      .begin()
+      .await?;
+
+    // Portals can only be created in orgs the user is a member of.
+    let portal = sqlx::query_as!(
+      DBPortal,
+      r#"
+      with _user as (select * from users where auth0id = $1)
+      insert into portals (name, org, owner_ids, vendor_ids, created_by, updated_by)
+      select $2, $3, $4, $5, _user.id, _user.id from _user
+      where $3 = any(_user.org_ids)
+      returning *;
+      "#,
+      auth0id,
+      new_portal.name,
+      new_portal.org,
+      &new_portal.owner_ids,
+      &new_portal.vendor_ids
+    )
+    .fetch_optional(&mut tx)
+    .await?
+    .ok_or_else(|| ApiError::NotFound(format!("Org {} not found", new_portal.org)).raise())?;
+
+    sqlx::query!(
+      r#"
+      insert into portalviews (portal_id, name, egress, access, created_by, updated_by)
+      values
+        ($1, 'Default Owner View', 'owner', 'public', $2, $2),
+        ($1, 'Default Vendor View', 'vendor', 'public', $2, $2);
+      "#,
+      portal.id,
+      portal.created_by
+    )
+    .execute(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(portal)
+  }
+
+  pub async fn update_portal(
+    &self,
+    auth0id: &str,
+    update_portal: DBUpdatePortal,
+  ) -> Result<DBPortal> {
+    let mut tx = self
+      .pool
+      .begin()
+      .await?;
+
+    let user = sqlx::query_as!(DBUser, "select * from users where auth0id = $1", auth0id)
+      .fetch_one(&mut tx)
+      .await?;
+
+    lock_portal_for_write(
+      &mut tx,
+      user.id,
+      update_portal.id,
+      PortalWrite::of_update(&update_portal),
+    )
+    .await?;
+
+    let portal = sqlx::query_as!(
+      DBPortal,
+      r#"
+      update portals
+        set
+          name = coalesce($3, name),
+          owner_ids = coalesce($4, owner_ids),
+          vendor_ids = coalesce($5, vendor_ids),
+          updated_by = $1
+      where id = $2
+      returning *;
+      "#,
+      user.id,
+      update_portal.id,
+      update_portal.name,
+      update_portal
+        .owner_ids
+        .as_deref(),
+      update_portal
+        .vendor_ids
+        .as_deref()
+    )
+    .fetch_one(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(portal)
+  }
+
+  // Adds the user with the given email to the owner or vendor side of the portal, creating an
+  // "invited" user for them if they haven't signed up yet. Inviting someone who is still "invited"
+  // to the same side again is allowed, so that the invitation can be sent again. Users that are
+  // still "invited" get an invitation expiring at `expires_at`, for binding their Auth0 identity.
+  pub async fn invite_user_to_portal(
+    &self,
+    auth0id: &str,
+    email: &str,
+    portal_id: Uuid,
+    egress: &str,
+    expires_at: DateTime<Utc>,
+  ) -> Result<(DBPortal, DBUser, Option<DBInvitation>)> {
+    let mut tx = self
+      .pool
+      .begin()
+      .await?;
+
+    let inviter = sqlx::query_as!(DBUser, "select * from users where auth0id = $1", auth0id)
+      .fetch_one(&mut tx)
+      .await?;
+
+    let write = match egress {
+      "owner" => PortalWrite::InviteOwner,
+      "vendor" => PortalWrite::InviteVendor,
+      _ => {
+        return Err(ApiError::Validation(format!("Unknown egress: {}", egress)).raise());
+      }
+    };
+
+    // Locked, so that concurrent invites to the portal don't drop each other's members.
+    let portal = lock_portal_for_write(&mut tx, inviter.id, portal_id, write).await?;
+
+    let existing_user = sqlx::query_as!(
+      DBUser,
+      "select * from users where lower(email) = lower($1) order by created_at limit 1",
+      email
+    )
+    .fetch_optional(&mut tx)
+    .await?;
+
+    let user = match existing_user {
+      Some(user) => user,
+      None => {
+        sqlx::query_as!(
+          DBUser,
+          r#"
+          insert into users (name, nickname, email, status, created_by, updated_by)
+          values ('', '', $1, 'invited', $2, $2)
+          returning *;
+          "#,
+          email,
+          inviter.id
+        )
+        .fetch_one(&mut tx)
+        .await?
+      }
+    };
+
+    let is_owner = portal
+      .owner_ids
+      .contains(&user.id);
+    let is_vendor = portal
+      .vendor_ids
+      .contains(&user.id);
+
+    let already_invited = match write {
+      PortalWrite::InviteOwner => is_owner,
+      _ => is_vendor,
+    };
+
+    if (is_owner || is_vendor) && !(already_invited && user.status == "invited") {
+      return Err(ApiError::Conflict(String::from("User already belongs to this portal")).raise());
+    }
+
+    let portal = if already_invited {
+      portal
+    } else {
+      sqlx::query_as!(
+        DBPortal,
+        r#"
+        update portals
+          set
+            owner_ids = case when $2 = 'owner' then array_append(owner_ids, $3) else owner_ids end,
+            vendor_ids = case when $2 = 'vendor' then array_append(vendor_ids, $3) else vendor_ids end,
+            updated_by = $4
+        where id = $1
+        returning *;
+        "#,
+        portal_id,
+        egress,
+        user.id,
+        inviter.id
+      )
+      .fetch_one(&mut tx)
+      .await?
+    };
+
+    // Every org has a portal role per egress for its invited users, created on the first invite.
+    sqlx::query!(
+      r#"
+      insert into roles (role_type, perms, org_id, egress, created_by, updated_by)
+      values ('Portal', $3, $1, $2, $4, $4)
+      on conflict (org_id, egress) where egress is not null do nothing;
+      "#,
+      portal.org,
+      egress,
+      invitee_perms(egress),
+      inviter.id
+    )
+    .execute(&mut tx)
+    .await?;
+
+    let role = sqlx::query_as!(
+      DBRole,
+      "select * from roles where org_id = $1 and egress = $2",
+      portal.org,
+      egress
+    )
+    .fetch_one(&mut tx)
+    .await?;
+
+    // Portal members are members of the portal's org as well, and need the role to get at it.
+    let user = sqlx::query_as!(
+      DBUser,
+      r#"
+      update users
+        set
+          org_ids = case when $2 = any(org_ids) then org_ids else array_append(org_ids, $2) end,
+          role_ids = case when $3 = any(role_ids) then role_ids else array_append(role_ids, $3) end,
+          updated_by = $4
+      where id = $1
+      returning *;
+      "#,
+      user.id,
+      portal.org,
+      role.id,
+      inviter.id
+    )
+    .fetch_one(&mut tx)
+    .await?;
+
+    let invitation = if user.status == "invited" {
+      let invitation = sqlx::query_as!(
+        DBInvitation,
+        r#"
+        insert into invitations (user_id, portal_id, egress, expires_at, created_by, updated_by)
+        values ($1, $2, $3, $4, $5, $5)
+        returning *;
+        "#,
+        user.id,
+        portal_id,
+        egress,
+        expires_at,
+        inviter.id
+      )
+      .fetch_one(&mut tx)
+      .await?;
+
+      Some(invitation)
+    } else {
+      None
+    };
+
+    tx.commit().await?;
+
+    Ok((portal, user, invitation))
+  }
+
+  // Removes the portal and everything that hangs off of it.
+  pub async fn delete_portal(&self, auth0id: &str, portal_id: Uuid) -> Result<DBPortal> {
+    let mut tx = self
+      .pool
+      .begin()
+      .await?;
+
+    let user = sqlx::query_as!(DBUser, "select * from users where auth0id = $1", auth0id)
+      .fetch_one(&mut tx)
+      .await?;
+
+    lock_portal_for_write(&mut tx, user.id, portal_id, PortalWrite::Delete).await?;
+
+    sqlx::query!("delete from cells where portal_id = $1", portal_id)
+      .execute(&mut tx)
+      .await?;
+
+    sqlx::query!("delete from dimensions where portal_id = $1", portal_id)
+      .execute(&mut tx)
+      .await?;
+
+    sqlx::query!("delete from blocks where portal_id = $1", portal_id)
+      .execute(&mut tx)
+      .await?;
+
+    sqlx::query!("delete from portalviews where portal_id = $1", portal_id)
+      .execute(&mut tx)
+      .await?;
+
+    let portal = sqlx::query_as!(
+      DBPortal,
+      "delete from portals where id = $1 returning *",
+      portal_id
+    )
+    .fetch_one(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(portal)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::graphql::authorization::check_permission;
+  use crate::graphql::schema::role::RolePerms;
+
+  fn portal(owner_id: Uuid, vendor_id: Uuid) -> DBPortal {
+    let now = Utc::now();
+
+    DBPortal {
+      id: Uuid::new_v4(),
+      name: String::from("Quarterly numbers"),
+      org: Uuid::new_v4(),
+      owner_ids: vec![owner_id],
+      vendor_ids: vec![vendor_id],
+      created_at: now,
+      created_by: owner_id,
+      updated_at: now,
+      updated_by: owner_id,
+    }
+  }
+
+  fn update(owner_ids: Option<Vec<Uuid>>) -> DBUpdatePortal {
+    DBUpdatePortal {
+      id: Uuid::new_v4(),
+      name: Some(String::from("Renamed")),
+      owner_ids,
+      vendor_ids: None,
+    }
+  }
+
+  fn is_forbidden(result: Result<()>) -> bool {
+    matches!(
+      result.map_err(ApiError::from),
+      Err(ApiError::Forbidden(Permission::EditPortal))
+    )
+  }
+
+  #[test]
+  fn owners_can_do_anything() {
+    let (owner_id, vendor_id) = (Uuid::new_v4(), Uuid::new_v4());
+    let portal = portal(owner_id, vendor_id);
+
+    for write in [
+      PortalWrite::Rename,
+      PortalWrite::EditMembers,
+      PortalWrite::Delete,
+      PortalWrite::InviteOwner,
+      PortalWrite::InviteVendor,
+    ]
+    .iter()
+    {
+      assert!(authorize_portal_write(&portal, owner_id, *write).is_ok());
+    }
+  }
+
+  #[test]
+  fn vendors_are_refused_owner_writes() {
+    let (owner_id, vendor_id) = (Uuid::new_v4(), Uuid::new_v4());
+    let portal = portal(owner_id, vendor_id);
+
+    assert!(is_forbidden(authorize_portal_write(
+      &portal,
+      vendor_id,
+      PortalWrite::EditMembers
+    )));
+    assert!(is_forbidden(authorize_portal_write(
+      &portal,
+      vendor_id,
+      PortalWrite::Delete
+    )));
+    assert!(is_forbidden(authorize_portal_write(
+      &portal,
+      vendor_id,
+      PortalWrite::InviteOwner
+    )));
+  }
+
+  #[test]
+  fn vendors_can_rename_and_invite_vendors() {
+    let (owner_id, vendor_id) = (Uuid::new_v4(), Uuid::new_v4());
+    let portal = portal(owner_id, vendor_id);
+
+    assert!(authorize_portal_write(&portal, vendor_id, PortalWrite::Rename).is_ok());
+    assert!(authorize_portal_write(&portal, vendor_id, PortalWrite::InviteVendor).is_ok());
+  }
+
+  #[test]
+  fn non_members_do_not_see_the_portal() {
+    let portal = portal(Uuid::new_v4(), Uuid::new_v4());
+
+    let result = authorize_portal_write(&portal, Uuid::new_v4(), PortalWrite::Rename);
+
+    assert!(matches!(
+      result.map_err(ApiError::from),
+      Err(ApiError::NotFound(_))
+    ));
+  }
+
+  #[test]
+  fn updates_to_the_member_lists_are_member_edits() {
+    assert_eq!(PortalWrite::of_update(&update(None)), PortalWrite::Rename);
+    assert_eq!(
+      PortalWrite::of_update(&update(Some(Vec::new()))),
+      PortalWrite::EditMembers
+    );
+  }
+  fn invitee_role(egress: &str) -> Vec<RolePerms> {
+    let now = Utc::now();
+    let user_id = Uuid::new_v4();
+
+    let role = DBRole {
+      id: Uuid::new_v4(),
+      role_type: String::from("Portal"),
+      perms: invitee_perms(egress),
+      org_id: Some(Uuid::new_v4()),
+      egress: Some(String::from(egress)),
+      created_at: now,
+      created_by: user_id,
+      updated_at: now,
+      updated_by: user_id,
+    };
+
+    vec![RolePerms::from_db_role(&role).unwrap()]
+  }
+
+  #[test]
+  fn invited_users_can_view_the_portal() {
+    for egress in ["owner", "vendor"].iter() {
+      assert!(check_permission(&invitee_role(egress), Permission::ViewPortal).is_ok());
+    }
+  }
+
+  #[test]
+  fn only_invited_owners_can_edit_the_portal() {
+    assert!(check_permission(&invitee_role("owner"), Permission::EditPortal).is_ok());
+    assert!(check_permission(&invitee_role("vendor"), Permission::EditPortal).is_err());
+  }
 }
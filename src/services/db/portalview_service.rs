@@ -1,5 +1,6 @@
 use std::any;
 
+use super::pagination::{cursor_params, DBCursor, DBPage};
 use super::DB;
 
 use anyhow::Result;
@@ -7,6 +8,9 @@ use chrono::{DateTime, Utc};
 
 use uuid::Uuid;
 
+use crate::errors::ApiError;
+use crate::graphql::schema::portalview::{NewPortalView, UpdatePortalView};
+
 #[derive(Debug, Serialize)]
 pub struct DBPortalView {
   pub id: Uuid,
@@ -33,17 +37,227 @@ pub struct DBPortalView {
   pub updated_by: Uuid,
 }
 
+#[derive(Debug, Serialize)]
+pub struct DBNewPortalView {
+  pub portal_id: Uuid,
+
+  pub name: String,
+
+  pub egress: String,
+
+  pub access: String,
+}
+
+impl From<NewPortalView> for DBNewPortalView {
+  fn from(new_portalview: NewPortalView) -> Self {
+    DBNewPortalView {
+      portal_id: new_portalview.portal_id,
+      name: new_portalview.name,
+      egress: new_portalview
+        .egress
+        .to_string(),
+      access: new_portalview
+        .access
+        .to_string(),
+    }
+  }
+}
+
+#[derive(Debug, Serialize)]
+pub struct DBUpdatePortalView {
+  pub id: Uuid,
+
+  pub name: Option<String>,
+
+  pub egress: Option<String>,
+
+  pub access: Option<String>,
+}
+
+impl From<UpdatePortalView> for DBUpdatePortalView {
+  fn from(update_portalview: UpdatePortalView) -> Self {
+    DBUpdatePortalView {
+      id: update_portalview.id,
+      name: update_portalview.name,
+      egress: update_portalview
+        .egress
+        .map(|e| e.to_string()),
+      access: update_portalview
+        .access
+        .map(|a| a.to_string()),
+    }
+  }
+}
+
 impl DB {
-  pub async fn get_portal_views(&self, portal_id: Uuid) -> Result<Vec<DBPortalView>> {
+  // Only returns the portal view if the user is an owner or vendor of the view's portal.
+  pub async fn get_auth0_user_portal_view(
+    &self,
+    auth0_user_id: &str,
+    portal_view_id: Uuid,
+  ) -> Result<DBPortalView> {
     sqlx::query_as!(
+      DBPortalView,
+      r#"
+      with _user as (select * from users where auth0id = $1)
+      select portalviews.* from portalviews
+      inner join portals on portals.id = portalviews.portal_id, _user
+      where portalviews.id = $2 and
+      (_user.id = any(portals.owner_ids) or _user.id = any(portals.vendor_ids));
+      "#,
+      auth0_user_id,
+      portal_view_id
+    )
+    .fetch_optional(&self.pool)
+    .await?
+    .ok_or_else(|| ApiError::NotFound(format!("Portal view {} not found", portal_view_id)).raise())
+  }
+
+  // The portal views of portals the user is an owner or vendor of, out of the given ones.
+  pub async fn get_auth0_user_portal_views_by_ids(
+    &self,
+    auth0_user_id: &str,
+    portal_view_ids: &[Uuid],
+  ) -> Result<Vec<DBPortalView>> {
+    sqlx::query_as!(
+      DBPortalView,
+      r#"
+      with _user as (select * from users where auth0id = $1)
+      select portalviews.* from portalviews
+      inner join portals on portals.id = portalviews.portal_id, _user
+      where portalviews.id = any($2) and
+      (_user.id = any(portals.owner_ids) or _user.id = any(portals.vendor_ids));
+      "#,
+      auth0_user_id,
+      portal_view_ids
+    )
+    .fetch_all(&self.pool)
+    .await
+    .map_err(anyhow::Error::from)
+  }
+
+  pub async fn get_portal_views(
+    &self,
+    portal_id: Uuid,
+    first: i64,
+    after: Option<DBCursor>,
+  ) -> Result<DBPage<DBPortalView>> {
+    let (after_created_at, after_id, _) = cursor_params(after);
+
+    let rows = sqlx::query_as!(
       DBPortalView,
       r#"
       select * from portalviews where portal_id = $1
+      and ($2::timestamptz is null or (created_at, id) > ($2, $3::uuid))
+      order by created_at, id
+      limit $4
       "#,
-      portal_id
+      portal_id,
+      after_created_at,
+      after_id,
+      first + 1
     )
     .fetch_all(&self.pool)
+    .await?;
+
+    Ok(DBPage::from_rows(rows, first))
+  }
+
+  pub async fn create_portal_view(
+    &self,
+    auth0id: &str,
+    new_portalview: DBNewPortalView,
+  ) -> Result<DBPortalView> {
+    self
+      .get_auth0_user_portal(auth0id, new_portalview.portal_id)
+      .await?;
+
+    sqlx::query_as!(
+      DBPortalView,
+      r#"
+      with _user as (select * from users where auth0id = $1)
+      insert into portalviews (portal_id, name, egress, access, created_by, updated_by)
+      values ($2, $3, $4, $5, (select id from _user), (select id from _user))
+      returning *;
+      "#,
+      auth0id,
+      new_portalview.portal_id,
+      new_portalview.name,
+      new_portalview.egress,
+      new_portalview.access
+    )
+    .fetch_one(&self.pool)
     .await
     .map_err(anyhow::Error::from)
   }
+
+  pub async fn update_portal_view(
+    &self,
+    auth0id: &str,
+    update_portalview: DBUpdatePortalView,
+  ) -> Result<DBPortalView> {
+    sqlx::query_as!(
+      DBPortalView,
+      r#"
+      with _user as (select * from users where auth0id = $1)
+      update portalviews
+        set
+          name = coalesce($3, name),
+          egress = coalesce($4, egress),
+          access = coalesce($5, access),
+          updated_by = (select id from _user)
+      where id = $2 and exists (
+        select 1 from portals
+        where portals.id = portalviews.portal_id and
+        (select id from _user) = any(portals.owner_ids || portals.vendor_ids)
+      )
+      returning *;
+      "#,
+      auth0id,
+      update_portalview.id,
+      update_portalview.name,
+      update_portalview.egress,
+      update_portalview.access
+    )
+    .fetch_optional(&self.pool)
+    .await?
+    .ok_or_else(|| {
+      ApiError::NotFound(format!("Portal view {} not found", update_portalview.id)).raise()
+    })
+  }
+
+  // Blocks only ever live in a single portal view, so they go along with it.
+  pub async fn delete_portal_view(
+    &self,
+    auth0id: &str,
+    portal_view_id: Uuid,
+  ) -> Result<DBPortalView> {
+    self
+      .get_auth0_user_portal_view(auth0id, portal_view_id)
+      .await?;
+
+    let mut tx = self
+      .pool
+      .begin()
+      .await?;
+
+    sqlx::query!(
+      "delete from blocks where portal_view_id = $1",
+      portal_view_id
+    )
+    .execute(&mut tx)
+    .await?;
+
+    let portalview = sqlx::query_as!(
+      DBPortalView,
+      "delete from portalviews where id = $1 returning *",
+      portal_view_id
+    )
+    .fetch_one(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(portalview)
+  }
 }
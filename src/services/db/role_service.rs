@@ -15,6 +15,12 @@ pub struct DBRole {
 
   pub perms: serde_json::Value,
 
+  // Only set on the roles of invited users, see invite_user_to_portal.
+  #[serde(rename = "orgId")]
+  pub org_id: Option<Uuid>,
+
+  pub egress: Option<String>,
+
   #[serde(rename = "createdAt")]
   pub created_at: DateTime<Utc>,
 
@@ -50,6 +56,13 @@ impl DB {
       .map_err(anyhow::Error::from)
   }
 
+  pub async fn get_roles(&self, role_ids: &[Uuid]) -> Result<Vec<DBRole>> {
+    sqlx::query_as!(DBRole, "select * from roles where id = any($1)", role_ids)
+      .fetch_all(&self.pool)
+      .await
+      .map_err(anyhow::Error::from)
+  }
+
   pub async fn create_role(&self, auth0id: &str, new_role: DBNewRole) -> Result<DBRole> {
     sqlx::query_as!(
       DBRole,
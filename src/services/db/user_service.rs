@@ -128,6 +128,35 @@ impl DB {
       .map_err(anyhow::Error::from)
   }
 
+  // Users the requesting user can see: themselves, and whoever shares an org or a portal with them.
+  pub async fn get_auth0_user_visible_users(
+    &self,
+    auth0_user_id: &str,
+    user_ids: &[Uuid],
+  ) -> Result<Vec<DBUser>> {
+    sqlx::query_as!(
+      DBUser,
+      r#"
+      with _user as (select * from users where auth0id = $1)
+      select users.* from users, _user
+      where users.id = any($2) and (
+        users.id = _user.id or
+        users.org_ids && _user.org_ids or
+        exists (
+          select 1 from portals
+          where _user.id = any(portals.owner_ids || portals.vendor_ids) and
+          users.id = any(portals.owner_ids || portals.vendor_ids)
+        )
+      );
+      "#,
+      auth0_user_id,
+      user_ids
+    )
+    .fetch_all(&self.pool)
+    .await
+    .map_err(anyhow::Error::from)
+  }
+
   pub async fn get_user_by_auth0_id(&self, auth0_user_id: &str) -> Result<DBUser> {
     sqlx::query_as!(
       DBUser,
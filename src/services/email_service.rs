@@ -1,51 +1,230 @@
-// use crate::utils::async_block::{ BlockError };
-// use serde_json::json;
+use std::str::FromStr;
+use std::sync::Arc;
+#[cfg(test)]
+use std::sync::Mutex;
 
-// use rusoto_core::Region;
-// use rusoto_ses::{ Ses, SesClient, ListTemplatesRequest, ListTemplatesResponse, SendTemplatedEmailRequest, Destination, Message };
+use anyhow::Result;
+use async_trait::async_trait;
+use rusoto_core::Region;
+use rusoto_ses::{Destination, SendTemplatedEmailRequest, Ses, SesClient};
+use serde_json::json;
 
-// // Docs: https://docs.rs/rusoto_ses/0.43.0/rusoto_ses/#structs
+use crate::errors::ApiError;
 
-// pub async fn send_invitation_email() -> Result<(), BlockError> {
-//   println!("In send_invitation_email");
+pub const INVITATION_TEMPLATE: &str = "inviteNewUserToPortal";
 
-//   let client = SesClient::new(Region::UsWest2);
+// An email rendered from a template that lives with the email provider.
+#[derive(Debug, Clone, Serialize)]
+pub struct TemplatedEmail {
+  pub to: String,
 
-//   let req = ListTemplatesRequest {
-//     max_items: Some(100),
-//     next_token: None,
-//   };
+  pub template: String,
 
-//   let result: ListTemplatesResponse = client.list_templates(req).await.unwrap();
+  pub template_data: serde_json::Value,
+}
 
-//   println!("email result: {:?}", result);
-//   let result = client.list_verified_email_addresses().await.unwrap();
+// Everything that sends email goes through this, so that it can be swapped out, ie. for capturing
+// outgoing email instead of sending it.
+#[async_trait]
+pub trait EmailService: Send + Sync {
+  async fn send_templated_email(&self, email: TemplatedEmail) -> Result<()>;
+}
 
-  
-//   let tmpl_data = json!({
-//     "user": "Another Person",
-//   });
+// Sends the invitation to a portal. `token` is only there for users that haven't signed up yet.
+pub async fn send_invitation(
+  email_service: &dyn EmailService,
+  to: &str,
+  portal_name: &str,
+  inviter_name: &str,
+  egress: &str,
+  token: Option<&str>,
+) -> Result<()> {
+  email_service
+    .send_templated_email(TemplatedEmail {
+      to: String::from(to),
+      template: String::from(INVITATION_TEMPLATE),
+      template_data: json!({
+        "email": to,
+        "portalName": portal_name,
+        "inviterName": inviter_name,
+        "egress": egress,
+        "token": token,
+      }),
+    })
+    .await
+}
 
-//   let a = SendTemplatedEmailRequest {
-//     destination: Destination {
-//     bcc_addresses: None,
-//     cc_addresses: None,
-//     to_addresses: Some(vec![String::from("sinexo2900@psk3n.com")]),
-//     },
-//     source: String::from("broch@torus.rocks"),
-//     template: String::from("inviteNewUserToPortal"),
-//     template_data: tmpl_data.to_string(),
-//     ..Default::default()
-//   };
+// Records emails instead of sending them.
+#[cfg(test)]
+#[derive(Default)]
+pub struct CapturingEmailService {
+  pub sent: Mutex<Vec<TemplatedEmail>>,
+}
 
-//   match client.send_templated_email(a).await {
-//     Ok(_) => println!("email was sent!!!"),
-//     Err(err) => println!("email wasn't sent....: {:?}", err),
-//   };
-    
+#[cfg(test)]
+#[async_trait]
+impl EmailService for CapturingEmailService {
+  async fn send_templated_email(&self, email: TemplatedEmail) -> Result<()> {
+    self
+      .sent
+      .lock()
+      .unwrap()
+      .push(email);
 
-//   // println!("sent response???: {:?}", r);
+    Ok(())
+  }
+}
 
-//   println!("verified emails??: {:?}", result);
-//   Ok(())
-// }
+pub struct SesEmailService {
+  client: SesClient,
+
+  // Verified SES address that emails are sent from.
+  source: String,
+}
+
+impl SesEmailService {
+  // SES_REGION defaults to us-west-2, where the templates are. EMAIL_SOURCE is required.
+  pub fn from_env() -> std::result::Result<Self, String> {
+    let (region, source) = SesEmailService::parse(
+      std::env::var("SES_REGION")
+        .ok()
+        .as_deref(),
+      std::env::var("EMAIL_SOURCE")
+        .ok()
+        .as_deref(),
+    )?;
+
+    println!("email: sending as {} through {}", source, region.name());
+
+    Ok(SesEmailService {
+      client: SesClient::new(region),
+      source,
+    })
+  }
+
+  fn parse(
+    region: Option<&str>,
+    source: Option<&str>,
+  ) -> std::result::Result<(Region, String), String> {
+    let region = match region {
+      Some(region) => Region::from_str(region)
+        .map_err(|_| format!("SES_REGION must be an AWS region, got \"{}\"", region))?,
+      None => Region::UsWest2,
+    };
+
+    let source = source
+      .filter(|source| !source.is_empty())
+      .ok_or_else(|| String::from("EMAIL_SOURCE is not set"))?;
+
+    Ok((region, String::from(source)))
+  }
+}
+
+#[async_trait]
+impl EmailService for SesEmailService {
+  async fn send_templated_email(&self, email: TemplatedEmail) -> Result<()> {
+    let req = SendTemplatedEmailRequest {
+      destination: Destination {
+        bcc_addresses: None,
+        cc_addresses: None,
+        to_addresses: Some(vec![email.to]),
+      },
+      source: self.source.clone(),
+      template: email.template,
+      template_data: email
+        .template_data
+        .to_string(),
+      ..Default::default()
+    };
+
+    self
+      .client
+      .send_templated_email(req)
+      .await
+      .map(|_| ())
+      .map_err(|err| ApiError::Upstream(Arc::new(anyhow::Error::new(err))).raise())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use futures::executor::block_on;
+
+  #[test]
+  fn invitations_go_to_the_invited_address() {
+    let emails = CapturingEmailService::default();
+
+    block_on(send_invitation(
+      &emails,
+      "invited@example.com",
+      "Quarterly numbers",
+      "Ada",
+      "Vendor",
+      Some("token"),
+    ))
+    .unwrap();
+
+    let sent = emails
+      .sent
+      .lock()
+      .unwrap();
+
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].to, "invited@example.com");
+    assert_eq!(sent[0].template, INVITATION_TEMPLATE);
+    assert_eq!(
+      sent[0].template_data,
+      json!({
+        "email": "invited@example.com",
+        "portalName": "Quarterly numbers",
+        "inviterName": "Ada",
+        "egress": "Vendor",
+        "token": "token",
+      })
+    );
+  }
+
+  #[test]
+  fn ses_config_defaults_to_us_west_2() {
+    let (region, source) = SesEmailService::parse(None, Some("portals@example.com")).unwrap();
+
+    assert_eq!(region, Region::UsWest2);
+    assert_eq!(source, "portals@example.com");
+
+    let (region, _) =
+      SesEmailService::parse(Some("eu-west-1"), Some("portals@example.com")).unwrap();
+
+    assert_eq!(region, Region::EuWest1);
+  }
+
+  #[test]
+  fn malformed_ses_config_is_an_error() {
+    assert!(SesEmailService::parse(None, None).is_err());
+    assert!(SesEmailService::parse(Some("mars-1"), Some("portals@example.com")).is_err());
+  }
+
+  #[test]
+  fn signed_up_users_are_invited_without_a_token() {
+    let emails = CapturingEmailService::default();
+
+    block_on(send_invitation(
+      &emails,
+      "member@example.com",
+      "Quarterly numbers",
+      "Ada",
+      "Owner",
+      None,
+    ))
+    .unwrap();
+
+    let sent = emails
+      .sent
+      .lock()
+      .unwrap();
+
+    assert_eq!(sent[0].to, "member@example.com");
+    assert_eq!(sent[0].template_data["token"], serde_json::Value::Null);
+  }
+}
@@ -0,0 +1,1178 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+
+use futures::lock::Mutex;
+use uuid::Uuid;
+
+use crate::graphql::schema::cell::{CellTypes, FormulaCell, NumberCell};
+use crate::services::db::cell_service::DBCell;
+use crate::services::db::DB;
+
+// Formulas are arithmetic over numbers and references to other cells of the same portal:
+//
+//   cell:<cell id>           the value of a Number or Formula cell
+//   row:<dimension id>       every cell in the row, only inside a function
+//   column:<dimension id>    every cell in the column, only inside a function
+//
+// combined with +, -, *, /, parentheses and the functions SUM, AVG, MIN, MAX and COUNT,
+// ie. `SUM(row:<dimension id>) * 1.1`. Cells of a row or column that don't hold a number are
+// skipped, and a formula's own cell is never part of its rows or columns.
+
+// Upper bound on the cells a formula can depend on, directly or through other formulas.
+pub const MAX_DEPENDENCIES: usize = 10_000;
+
+// Upper bound on formulas referencing formulas, evaluation recurses once per level.
+pub const MAX_DEPTH: usize = 32;
+
+// Upper bound on how deeply a single formula nests, counting parentheses, functions, negations
+// and operators. Parsing and evaluation recurse once per level.
+pub const MAX_NESTING: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Function {
+  Sum,
+  Avg,
+  Min,
+  Max,
+  Count,
+}
+
+impl FromStr for Function {
+  type Err = ();
+
+  fn from_str(name: &str) -> Result<Self, Self::Err> {
+    match name
+      .to_uppercase()
+      .as_str()
+    {
+      "SUM" => Ok(Function::Sum),
+      "AVG" => Ok(Function::Avg),
+      "MIN" => Ok(Function::Min),
+      "MAX" => Ok(Function::Max),
+      "COUNT" => Ok(Function::Count),
+      _ => Err(()),
+    }
+  }
+}
+
+impl fmt::Display for Function {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let name = match self {
+      Function::Sum => "SUM",
+      Function::Avg => "AVG",
+      Function::Min => "MIN",
+      Function::Max => "MAX",
+      Function::Count => "COUNT",
+    };
+
+    write!(f, "{}", name)
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operator {
+  Add,
+  Subtract,
+  Multiply,
+  Divide,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+  Number(f64),
+
+  Cell(Uuid),
+
+  // Every cell that references the dimension, rows and columns are both dimensions.
+  Dimension(Uuid),
+
+  Negate(Box<Expr>),
+
+  Binary(Operator, Box<Expr>, Box<Expr>),
+
+  Call(Function, Vec<Expr>),
+}
+
+impl Expr {
+  fn collect_references(&self, cell_ids: &mut HashSet<Uuid>, dimension_ids: &mut HashSet<Uuid>) {
+    match self {
+      Expr::Number(_) => (),
+      Expr::Cell(id) => {
+        cell_ids.insert(*id);
+      }
+      Expr::Dimension(id) => {
+        dimension_ids.insert(*id);
+      }
+      Expr::Negate(expr) => expr.collect_references(cell_ids, dimension_ids),
+      Expr::Binary(_, left, right) => {
+        left.collect_references(cell_ids, dimension_ids);
+        right.collect_references(cell_ids, dimension_ids);
+      }
+      Expr::Call(_, args) => {
+        for arg in args {
+          arg.collect_references(cell_ids, dimension_ids);
+        }
+      }
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FormulaError {
+  Parse(String),
+
+  UnknownCell(Uuid),
+
+  NotANumber(Uuid),
+
+  DimensionOutsideFunction,
+
+  NoValues(Function),
+
+  DivisionByZero,
+
+  NotFinite,
+
+  Cycle(Vec<Uuid>),
+
+  // A formula cell this one depends on has an error.
+  Dependency(Uuid, Box<FormulaError>),
+
+  TooManyDependencies,
+
+  TooDeep,
+}
+
+impl fmt::Display for FormulaError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      FormulaError::Parse(msg) => write!(f, "Unable to parse formula: {}", msg),
+      FormulaError::UnknownCell(id) => write!(f, "Cell {} isn't in this portal", id),
+      FormulaError::NotANumber(id) => write!(f, "Cell {} doesn't hold a number", id),
+      FormulaError::DimensionOutsideFunction => write!(
+        f,
+        "Rows and columns can only be used inside a function, ie. SUM(row:<id>)"
+      ),
+      FormulaError::NoValues(function) => write!(f, "{} of no values", function),
+      FormulaError::DivisionByZero => write!(f, "Division by zero"),
+      FormulaError::NotFinite => write!(f, "Result is too large"),
+      FormulaError::Cycle(ids) => {
+        let path = ids
+          .iter()
+          .map(|id| id.to_string())
+          .collect::<Vec<String>>()
+          .join(" -> ");
+
+        write!(f, "Circular reference: {}", path)
+      }
+      FormulaError::Dependency(id, err) => write!(f, "Cell {}: {}", id, err),
+      FormulaError::TooManyDependencies => {
+        write!(f, "Formula depends on more than {} cells", MAX_DEPENDENCIES)
+      }
+      FormulaError::TooDeep => write!(
+        f,
+        "Formula references more than {} levels of formulas",
+        MAX_DEPTH
+      ),
+    }
+  }
+}
+
+// Parsing
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+  Number(f64),
+  Name(String),
+  Reference(String, Uuid),
+  Plus,
+  Minus,
+  Star,
+  Slash,
+  OpenParen,
+  CloseParen,
+  Comma,
+}
+
+impl fmt::Display for Token {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Token::Number(number) => write!(f, "{}", number),
+      Token::Name(name) => write!(f, "{}", name),
+      Token::Reference(kind, id) => write!(f, "{}:{}", kind, id),
+      Token::Plus => write!(f, "+"),
+      Token::Minus => write!(f, "-"),
+      Token::Star => write!(f, "*"),
+      Token::Slash => write!(f, "/"),
+      Token::OpenParen => write!(f, "("),
+      Token::CloseParen => write!(f, ")"),
+      Token::Comma => write!(f, ","),
+    }
+  }
+}
+
+fn take_while(chars: &[char], start: usize, pred: impl Fn(char) -> bool) -> (String, usize) {
+  let end = chars[start..]
+    .iter()
+    .position(|c| !pred(*c))
+    .map(|offset| start + offset)
+    .unwrap_or_else(|| chars.len());
+
+  (
+    chars[start..end]
+      .iter()
+      .collect(),
+    end,
+  )
+}
+
+fn tokenize(formula: &str) -> Result<Vec<Token>, FormulaError> {
+  let chars: Vec<char> = formula
+    .chars()
+    .collect();
+  let mut tokens = Vec::new();
+  let mut i = 0;
+
+  while i < chars.len() {
+    let token = match chars[i] {
+      c if c.is_whitespace() => {
+        i += 1;
+        continue;
+      }
+      '+' => Token::Plus,
+      '-' => Token::Minus,
+      '*' => Token::Star,
+      '/' => Token::Slash,
+      '(' => Token::OpenParen,
+      ')' => Token::CloseParen,
+      ',' => Token::Comma,
+      c if c.is_ascii_digit() || c == '.' => {
+        let (text, end) = take_while(&chars, i, |c| c.is_ascii_digit() || c == '.');
+
+        let number = text
+          .parse::<f64>()
+          .map_err(|_| FormulaError::Parse(format!("invalid number \"{}\"", text)))?;
+
+        tokens.push(Token::Number(number));
+        i = end;
+        continue;
+      }
+      c if c.is_ascii_alphabetic() => {
+        let (name, end) = take_while(&chars, i, |c| c.is_ascii_alphanumeric() || c == '_');
+        i = end;
+
+        if chars.get(i) == Some(&':') {
+          // Ids are 36 characters, so that ie. `cell:<id>-1` still works without spaces.
+          let (text, _) = take_while(&chars, i + 1, |c| c.is_ascii_hexdigit() || c == '-');
+          let text: String = text
+            .chars()
+            .take(36)
+            .collect();
+          i += 1 + text.len();
+
+          let id = Uuid::parse_str(&text)
+            .map_err(|_| FormulaError::Parse(format!("invalid id \"{}\" in {}:", text, name)))?;
+
+          tokens.push(Token::Reference(name.to_lowercase(), id));
+        } else {
+          tokens.push(Token::Name(name));
+        }
+
+        continue;
+      }
+      c => return Err(FormulaError::Parse(format!("unexpected \"{}\"", c))),
+    };
+
+    tokens.push(token);
+    i += 1;
+  }
+
+  Ok(tokens)
+}
+
+// Recursive descent, lowest precedence first:
+//   expression = term (("+" | "-") term)*
+//   term       = unary (("*" | "/") unary)*
+//   unary      = "-" unary | atom
+//   atom       = number | reference | name "(" expression ("," expression)* ")" | "(" expression ")"
+//
+// Every rule returns the expression along with its height, so that formulas nesting deeper than
+// MAX_NESTING are rejected before they are built, let alone evaluated.
+struct Parser {
+  tokens: Vec<Token>,
+  position: usize,
+
+  // Rules currently being parsed that nest, ie. open parentheses.
+  depth: usize,
+}
+
+type Parsed = Result<(Expr, usize), FormulaError>;
+
+fn too_deep() -> FormulaError {
+  FormulaError::Parse(String::from("formula is nested too deeply"))
+}
+
+fn check_height(height: usize) -> Result<usize, FormulaError> {
+  if height > MAX_NESTING {
+    Err(too_deep())
+  } else {
+    Ok(height)
+  }
+}
+
+impl Parser {
+  fn peek(&self) -> Option<&Token> {
+    self
+      .tokens
+      .get(self.position)
+  }
+
+  fn next(&mut self) -> Option<Token> {
+    let token = self
+      .tokens
+      .get(self.position)
+      .cloned();
+
+    self.position += 1;
+
+    token
+  }
+
+  fn expect(&mut self, expected: Token) -> Result<(), FormulaError> {
+    match self.next() {
+      Some(token) if token == expected => Ok(()),
+      Some(token) => Err(FormulaError::Parse(format!(
+        "expected \"{}\", found \"{}\"",
+        expected, token
+      ))),
+      None => Err(FormulaError::Parse(format!(
+        "expected \"{}\" before the end of the formula",
+        expected
+      ))),
+    }
+  }
+
+  // Runs a rule one level deeper.
+  fn nested(&mut self, rule: impl FnOnce(&mut Self) -> Parsed) -> Parsed {
+    if self.depth >= MAX_NESTING {
+      return Err(too_deep());
+    }
+
+    self.depth += 1;
+    let parsed = rule(self);
+    self.depth -= 1;
+
+    parsed
+  }
+
+  fn expression(&mut self) -> Parsed {
+    let (mut left, mut height) = self.term()?;
+
+    loop {
+      let operator = match self.peek() {
+        Some(Token::Plus) => Operator::Add,
+        Some(Token::Minus) => Operator::Subtract,
+        _ => return Ok((left, height)),
+      };
+
+      self.position += 1;
+
+      let (right, right_height) = self.term()?;
+      height = check_height(height.max(right_height) + 1)?;
+      left = Expr::Binary(operator, Box::new(left), Box::new(right));
+    }
+  }
+
+  fn term(&mut self) -> Parsed {
+    let (mut left, mut height) = self.unary()?;
+
+    loop {
+      let operator = match self.peek() {
+        Some(Token::Star) => Operator::Multiply,
+        Some(Token::Slash) => Operator::Divide,
+        _ => return Ok((left, height)),
+      };
+
+      self.position += 1;
+
+      let (right, right_height) = self.unary()?;
+      height = check_height(height.max(right_height) + 1)?;
+      left = Expr::Binary(operator, Box::new(left), Box::new(right));
+    }
+  }
+
+  fn unary(&mut self) -> Parsed {
+    if self.peek() == Some(&Token::Minus) {
+      self.position += 1;
+
+      let (expr, height) = self.nested(Self::unary)?;
+
+      return Ok((Expr::Negate(Box::new(expr)), check_height(height + 1)?));
+    }
+
+    self.atom()
+  }
+
+  fn atom(&mut self) -> Parsed {
+    match self.next() {
+      Some(Token::Number(number)) => Ok((Expr::Number(number), 1)),
+      Some(Token::Reference(kind, id)) => match kind.as_str() {
+        "cell" => Ok((Expr::Cell(id), 1)),
+        "row" | "column" => Ok((Expr::Dimension(id), 1)),
+        _ => Err(FormulaError::Parse(format!(
+          "unknown reference \"{}:\", expected cell:, row: or column:",
+          kind
+        ))),
+      },
+      Some(Token::Name(name)) => {
+        let function = Function::from_str(&name)
+          .map_err(|_| FormulaError::Parse(format!("unknown function \"{}\"", name)))?;
+
+        self.expect(Token::OpenParen)?;
+
+        let (first, mut height) = self.nested(Self::expression)?;
+        let mut args = vec![first];
+
+        while self.peek() == Some(&Token::Comma) {
+          self.position += 1;
+
+          let (arg, arg_height) = self.nested(Self::expression)?;
+          height = height.max(arg_height);
+          args.push(arg);
+        }
+
+        self.expect(Token::CloseParen)?;
+
+        Ok((Expr::Call(function, args), check_height(height + 1)?))
+      }
+      Some(Token::OpenParen) => {
+        let parsed = self.nested(Self::expression)?;
+
+        self.expect(Token::CloseParen)?;
+
+        Ok(parsed)
+      }
+      Some(token) => Err(FormulaError::Parse(format!("unexpected \"{}\"", token))),
+      None => Err(FormulaError::Parse(
+        "unexpected end of the formula".to_string(),
+      )),
+    }
+  }
+}
+
+pub fn parse(formula: &str) -> Result<Expr, FormulaError> {
+  let mut parser = Parser {
+    tokens: tokenize(formula)?,
+    position: 0,
+    depth: 0,
+  };
+
+  let (expr, _) = parser.expression()?;
+
+  match parser.peek() {
+    Some(token) => Err(FormulaError::Parse(format!("unexpected \"{}\"", token))),
+    None => Ok(expr),
+  }
+}
+
+// Evaluation
+
+// Dependency graphs loaded so far, per portal.
+pub type FormulaGraphs = Mutex<HashMap<Uuid, FormulaGraph>>;
+
+// Every cell the formulas of a portal depend on, directly or through other formulas. Dependencies
+// are loaded up front so that evaluating is synchronous, and the graph is kept for the rest of the
+// request so that every dependency is only queried once however many formulas read it.
+#[derive(Default)]
+pub struct FormulaGraph {
+  cells: HashMap<Uuid, DBCell>,
+
+  dimension_cells: HashMap<Uuid, Vec<Uuid>>,
+
+  formulas: HashMap<Uuid, Result<Expr, FormulaError>>,
+
+  // Formulas whose references haven't been loaded yet.
+  pending: Vec<Expr>,
+}
+
+impl FormulaGraph {
+  // Loads whatever `expr` depends on that isn't loaded yet, one round of queries per level of
+  // formulas referencing formulas. Returns false when that is more than MAX_DEPENDENCIES cells,
+  // the rest is left pending for the next formula.
+  async fn extend(&mut self, db: &DB, portal_id: Uuid, expr: &Expr) -> anyhow::Result<bool> {
+    let mut loaded_count = 0;
+
+    self
+      .pending
+      .push(expr.clone());
+
+    while !self
+      .pending
+      .is_empty()
+    {
+      if loaded_count > MAX_DEPENDENCIES {
+        return Ok(false);
+      }
+
+      let mut cell_ids = HashSet::new();
+      let mut dimension_ids = HashSet::new();
+
+      for expr in self
+        .pending
+        .drain(..)
+      {
+        expr.collect_references(&mut cell_ids, &mut dimension_ids);
+      }
+
+      let cell_ids: Vec<Uuid> = cell_ids
+        .into_iter()
+        .filter(|id| {
+          !self
+            .cells
+            .contains_key(id)
+        })
+        .collect();
+
+      let dimension_ids: Vec<Uuid> = dimension_ids
+        .into_iter()
+        .filter(|id| {
+          !self
+            .dimension_cells
+            .contains_key(id)
+        })
+        .collect();
+
+      let mut loaded = Vec::new();
+
+      if !cell_ids.is_empty() {
+        loaded.extend(
+          db.get_portal_cells(portal_id, &cell_ids)
+            .await?,
+        );
+      }
+
+      if !dimension_ids.is_empty() {
+        let dimension_cells = db
+          .get_portal_cells_by_dimensions(portal_id, &dimension_ids)
+          .await?;
+
+        self.insert_dimensions(&dimension_ids, &dimension_cells);
+
+        loaded.extend(dimension_cells);
+      }
+
+      loaded_count += self.insert_cells(loaded);
+    }
+
+    Ok(true)
+  }
+
+  // Records which of `cells` are in each of the dimensions.
+  fn insert_dimensions(&mut self, dimension_ids: &[Uuid], cells: &[DBCell]) {
+    for dimension_id in dimension_ids {
+      let ids = cells
+        .iter()
+        .filter(|cell| {
+          cell
+            .dimensions
+            .contains(dimension_id)
+        })
+        .map(|cell| cell.id)
+        .collect();
+
+      self
+        .dimension_cells
+        .insert(*dimension_id, ids);
+    }
+  }
+
+  // Adds the cells that aren't in the graph yet and queues up the references of their formulas.
+  // Returns how many were added.
+  fn insert_cells(&mut self, cells: Vec<DBCell>) -> usize {
+    let mut inserted = 0;
+
+    for cell in cells {
+      if self
+        .cells
+        .contains_key(&cell.id)
+      {
+        continue;
+      }
+
+      if cell.cell_type == CellTypes::Formula.to_string() {
+        let expr = serde_json::from_value::<FormulaCell>(cell.data.clone())
+          .map_err(|err| FormulaError::Parse(err.to_string()))
+          .and_then(|formula_cell| parse(&formula_cell.formula));
+
+        if let Ok(expr) = &expr {
+          self
+            .pending
+            .push(expr.clone());
+        }
+
+        self
+          .formulas
+          .insert(cell.id, expr);
+      }
+
+      self
+        .cells
+        .insert(cell.id, cell);
+
+      inserted += 1;
+    }
+
+    inserted
+  }
+}
+
+struct Evaluator<'a> {
+  graph: &'a FormulaGraph,
+
+  values: HashMap<Uuid, Result<Option<f64>, FormulaError>>,
+
+  // The formula cells currently being evaluated, for cycle detection.
+  visiting: Vec<Uuid>,
+}
+
+impl<'a> Evaluator<'a> {
+  // The number a cell holds, None for cells that don't hold one.
+  fn cell_value(&mut self, cell_id: Uuid) -> Result<Option<f64>, FormulaError> {
+    if let Some(value) = self
+      .values
+      .get(&cell_id)
+    {
+      return value.clone();
+    }
+
+    if let Some(start) = self
+      .visiting
+      .iter()
+      .position(|id| *id == cell_id)
+    {
+      let mut cycle = self.visiting[start..].to_vec();
+      cycle.push(cell_id);
+
+      return Err(FormulaError::Cycle(cycle));
+    }
+
+    if self.visiting.len() > MAX_DEPTH {
+      return Err(FormulaError::TooDeep);
+    }
+
+    let graph = self.graph;
+
+    let cell = graph
+      .cells
+      .get(&cell_id)
+      .ok_or(FormulaError::UnknownCell(cell_id))?;
+
+    let value = match CellTypes::from_str(&cell.cell_type) {
+      Ok(CellTypes::Number) => serde_json::from_value::<NumberCell>(cell.data.clone())
+        .map(|number_cell| Some(number_cell.number))
+        .map_err(|_| FormulaError::NotANumber(cell_id)),
+      Ok(CellTypes::Formula) => match graph
+        .formulas
+        .get(&cell_id)
+      {
+        Some(Ok(expr)) => {
+          self
+            .visiting
+            .push(cell_id);
+          let value = self.number(expr, cell_id);
+          self.visiting.pop();
+
+          value
+            .map(Some)
+            .map_err(|err| match err {
+              FormulaError::Cycle(_) | FormulaError::TooDeep => err,
+              err => FormulaError::Dependency(cell_id, Box::new(err)),
+            })
+        }
+        Some(Err(err)) => Err(FormulaError::Dependency(cell_id, Box::new(err.clone()))),
+        None => Err(FormulaError::UnknownCell(cell_id)),
+      },
+      _ => Ok(None),
+    };
+
+    self
+      .values
+      .insert(cell_id, value.clone());
+
+    value
+  }
+
+  // Values of the numeric cells of a row or column, leaving out the formula's own cell.
+  fn dimension_values(
+    &mut self,
+    dimension_id: Uuid,
+    owner_id: Uuid,
+  ) -> Result<Vec<f64>, FormulaError> {
+    let graph = self.graph;
+    let mut values = Vec::new();
+
+    for cell_id in graph
+      .dimension_cells
+      .get(&dimension_id)
+      .into_iter()
+      .flatten()
+    {
+      if *cell_id == owner_id {
+        continue;
+      }
+
+      if let Some(value) = self.cell_value(*cell_id)? {
+        values.push(value);
+      }
+    }
+
+    Ok(values)
+  }
+
+  fn number(&mut self, expr: &Expr, owner_id: Uuid) -> Result<f64, FormulaError> {
+    match expr {
+      Expr::Number(number) => Ok(*number),
+      Expr::Cell(cell_id) => self
+        .cell_value(*cell_id)?
+        .ok_or(FormulaError::NotANumber(*cell_id)),
+      Expr::Dimension(_) => Err(FormulaError::DimensionOutsideFunction),
+      Expr::Negate(expr) => Ok(-self.number(expr, owner_id)?),
+      Expr::Binary(operator, left, right) => {
+        let left = self.number(left, owner_id)?;
+        let right = self.number(right, owner_id)?;
+
+        match operator {
+          Operator::Add => Ok(left + right),
+          Operator::Subtract => Ok(left - right),
+          Operator::Multiply => Ok(left * right),
+          Operator::Divide if right == 0.0 => Err(FormulaError::DivisionByZero),
+          Operator::Divide => Ok(left / right),
+        }
+      }
+      Expr::Call(function, args) => {
+        let mut values = Vec::new();
+
+        for arg in args {
+          match arg {
+            Expr::Dimension(dimension_id) => {
+              values.extend(self.dimension_values(*dimension_id, owner_id)?)
+            }
+            arg => values.push(self.number(arg, owner_id)?),
+          }
+        }
+
+        apply(*function, &values)
+      }
+    }
+  }
+}
+
+fn apply(function: Function, values: &[f64]) -> Result<f64, FormulaError> {
+  match function {
+    Function::Sum => Ok(values.iter().sum()),
+    Function::Count => Ok(values.len() as f64),
+    _ if values.is_empty() => Err(FormulaError::NoValues(function)),
+    Function::Avg => Ok(
+      values
+        .iter()
+        .sum::<f64>()
+        / values.len() as f64,
+    ),
+    Function::Min => Ok(
+      values
+        .iter()
+        .cloned()
+        .fold(f64::INFINITY, f64::min),
+    ),
+    Function::Max => Ok(
+      values
+        .iter()
+        .cloned()
+        .fold(f64::NEG_INFINITY, f64::max),
+    ),
+  }
+}
+
+pub struct Evaluation {
+  pub value: Result<f64, FormulaError>,
+
+  // Cells the formula reads directly, including the cells of its rows and columns.
+  pub dependency_ids: Vec<Uuid>,
+}
+
+// Formula errors are part of the evaluation, only failing to load the dependencies is an Err.
+pub async fn evaluate_formula(
+  graphs: &FormulaGraphs,
+  db: &DB,
+  cell_id: Uuid,
+  portal_id: Uuid,
+  formula: &str,
+) -> anyhow::Result<Evaluation> {
+  let expr = match parse(formula) {
+    Ok(expr) => expr,
+    Err(err) => {
+      return Ok(Evaluation {
+        value: Err(err),
+        dependency_ids: Vec::new(),
+      })
+    }
+  };
+
+  let mut graphs = graphs.lock().await;
+
+  let graph = graphs
+    .entry(portal_id)
+    .or_default();
+
+  let loaded = graph
+    .extend(db, portal_id, &expr)
+    .await?;
+
+  let mut evaluation = evaluate(graph, cell_id, &expr);
+
+  if !loaded {
+    evaluation.value = Err(FormulaError::TooManyDependencies);
+  }
+
+  Ok(evaluation)
+}
+
+// Evaluates the formula of `cell_id` against a graph that holds all of its dependencies.
+fn evaluate(graph: &FormulaGraph, cell_id: Uuid, expr: &Expr) -> Evaluation {
+  let mut cell_ids = HashSet::new();
+  let mut dimension_ids = HashSet::new();
+  expr.collect_references(&mut cell_ids, &mut dimension_ids);
+
+  for dimension_id in dimension_ids {
+    cell_ids.extend(
+      graph
+        .dimension_cells
+        .get(&dimension_id)
+        .into_iter()
+        .flatten(),
+    );
+  }
+
+  cell_ids.remove(&cell_id);
+
+  let mut dependency_ids: Vec<Uuid> = cell_ids
+    .into_iter()
+    .collect();
+  dependency_ids.sort();
+
+  let mut evaluator = Evaluator {
+    graph,
+    values: HashMap::new(),
+    visiting: vec![cell_id],
+  };
+
+  let value = evaluator
+    .number(expr, cell_id)
+    .and_then(|value| {
+      if value.is_finite() {
+        Ok(value)
+      } else {
+        Err(FormulaError::NotFinite)
+      }
+    });
+
+  Evaluation {
+    value,
+    dependency_ids,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use chrono::Utc;
+  use serde_json::json;
+
+  fn cell(cell_type: CellTypes, dimensions: &[Uuid], data: serde_json::Value) -> DBCell {
+    let now = Utc::now();
+
+    DBCell {
+      id: Uuid::new_v4(),
+      portal_id: Uuid::nil(),
+      cell_type: cell_type.to_string(),
+      dimensions: dimensions.to_vec(),
+      data,
+      created_at: now,
+      created_by: Uuid::nil(),
+      updated_at: now,
+      updated_by: Uuid::nil(),
+    }
+  }
+
+  fn number(dimensions: &[Uuid], number: f64) -> DBCell {
+    cell(CellTypes::Number, dimensions, json!({ "number": number }))
+  }
+
+  fn formula(dimensions: &[Uuid], formula: &str) -> DBCell {
+    cell(
+      CellTypes::Formula,
+      dimensions,
+      json!({ "formula": formula }),
+    )
+  }
+
+  fn text(dimensions: &[Uuid]) -> DBCell {
+    cell(CellTypes::BasicText, dimensions, json!({ "text": "n/a" }))
+  }
+
+  fn graph(cells: Vec<DBCell>) -> FormulaGraph {
+    let mut dimension_ids: Vec<Uuid> = cells
+      .iter()
+      .flat_map(|cell| {
+        cell
+          .dimensions
+          .clone()
+      })
+      .collect();
+    dimension_ids.sort();
+    dimension_ids.dedup();
+
+    let mut graph = FormulaGraph::default();
+    graph.insert_dimensions(&dimension_ids, &cells);
+    graph.insert_cells(cells);
+
+    graph
+  }
+
+  // Evaluates a formula of a cell that isn't in the graph.
+  fn eval(graph: &FormulaGraph, formula: &str) -> Result<f64, FormulaError> {
+    let expr = parse(formula)?;
+
+    evaluate(graph, Uuid::new_v4(), &expr).value
+  }
+
+  fn eval_cell(graph: &FormulaGraph, cell_id: Uuid) -> Result<f64, FormulaError> {
+    let expr = graph.formulas[&cell_id].clone()?;
+
+    evaluate(graph, cell_id, &expr).value
+  }
+
+  #[test]
+  fn operators_follow_precedence() {
+    let graph = graph(Vec::new());
+
+    assert_eq!(eval(&graph, "1 + 2 * 3"), Ok(7.0));
+    assert_eq!(eval(&graph, "(1 + 2) * 3"), Ok(9.0));
+    assert_eq!(eval(&graph, "2 - 3 - 4"), Ok(-5.0));
+    assert_eq!(eval(&graph, "8 / 4 / 2"), Ok(1.0));
+    assert_eq!(eval(&graph, "-2 * 3 + 10 / 4"), Ok(-3.5));
+    assert_eq!(eval(&graph, "--1.5"), Ok(1.5));
+  }
+
+  #[test]
+  fn functions_aggregate_rows() {
+    let row = Uuid::new_v4();
+    let graph = graph(vec![
+      number(&[row], 1.0),
+      number(&[row], 2.0),
+      number(&[row], 3.0),
+      number(&[row], 4.0),
+      text(&[row]),
+    ]);
+
+    assert_eq!(eval(&graph, &format!("SUM(row:{})", row)), Ok(10.0));
+    assert_eq!(eval(&graph, &format!("AVG(row:{})", row)), Ok(2.5));
+    assert_eq!(eval(&graph, &format!("MIN(row:{})", row)), Ok(1.0));
+    assert_eq!(eval(&graph, &format!("MAX(row:{})", row)), Ok(4.0));
+    assert_eq!(eval(&graph, &format!("COUNT(row:{})", row)), Ok(4.0));
+    assert_eq!(
+      eval(&graph, &format!("sum(column:{}, 10) / 2", row)),
+      Ok(10.0)
+    );
+  }
+
+  #[test]
+  fn functions_of_no_values() {
+    let graph = graph(Vec::new());
+    let row = Uuid::new_v4();
+
+    assert_eq!(eval(&graph, &format!("SUM(row:{})", row)), Ok(0.0));
+    assert_eq!(eval(&graph, &format!("COUNT(row:{})", row)), Ok(0.0));
+    assert_eq!(
+      eval(&graph, &format!("AVG(row:{})", row)),
+      Err(FormulaError::NoValues(Function::Avg))
+    );
+  }
+
+  #[test]
+  fn formulas_leave_their_own_cell_out_of_their_rows() {
+    let row = Uuid::new_v4();
+    let total = formula(&[row], &format!("SUM(row:{})", row));
+    let total_id = total.id;
+    let graph = graph(vec![number(&[row], 1.0), number(&[row], 2.0), total]);
+
+    assert_eq!(eval_cell(&graph, total_id), Ok(3.0));
+  }
+
+  #[test]
+  fn bad_references() {
+    let row = Uuid::new_v4();
+    let label = text(&[row]);
+    let label_id = label.id;
+    let graph = graph(vec![label]);
+    let unknown = Uuid::new_v4();
+
+    assert_eq!(
+      eval(&graph, &format!("cell:{}", unknown)),
+      Err(FormulaError::UnknownCell(unknown))
+    );
+    assert_eq!(
+      eval(&graph, &format!("cell:{} + 1", label_id)),
+      Err(FormulaError::NotANumber(label_id))
+    );
+    assert_eq!(
+      eval(&graph, &format!("row:{} + 1", row)),
+      Err(FormulaError::DimensionOutsideFunction)
+    );
+    assert!(matches!(
+      eval(&graph, "cell:not-an-id"),
+      Err(FormulaError::Parse(_))
+    ));
+    assert!(matches!(
+      eval(&graph, &format!("sheet:{}", unknown)),
+      Err(FormulaError::Parse(_))
+    ));
+    assert!(matches!(
+      eval(&graph, "MEDIAN(1, 2)"),
+      Err(FormulaError::Parse(_))
+    ));
+    assert!(matches!(
+      eval(&graph, "(1 + 2"),
+      Err(FormulaError::Parse(_))
+    ));
+  }
+
+  #[test]
+  fn errors_of_referenced_formulas_name_the_cell() {
+    let broken = formula(&[], "1 / 0");
+    let broken_id = broken.id;
+    let graph = graph(vec![broken]);
+
+    assert_eq!(
+      eval(&graph, &format!("cell:{} + 1", broken_id)),
+      Err(FormulaError::Dependency(
+        broken_id,
+        Box::new(FormulaError::DivisionByZero)
+      ))
+    );
+  }
+
+  #[test]
+  fn self_reference_is_a_cycle() {
+    let mut own = formula(&[], "");
+    own.data = json!({ "formula": format!("cell:{} + 1", own.id) });
+    let own_id = own.id;
+    let graph = graph(vec![own]);
+
+    assert_eq!(
+      eval_cell(&graph, own_id),
+      Err(FormulaError::Cycle(vec![own_id, own_id]))
+    );
+  }
+
+  #[test]
+  fn mutual_references_are_a_cycle() {
+    let mut a = formula(&[], "");
+    let b = formula(&[], &format!("cell:{} * 2", a.id));
+    a.data = json!({ "formula": format!("cell:{} + 1", b.id) });
+    let (a_id, b_id) = (a.id, b.id);
+    let graph = graph(vec![a, b]);
+
+    assert_eq!(
+      eval_cell(&graph, a_id),
+      Err(FormulaError::Cycle(vec![a_id, b_id, a_id]))
+    );
+    assert_eq!(
+      eval_cell(&graph, b_id),
+      Err(FormulaError::Cycle(vec![b_id, a_id, b_id]))
+    );
+  }
+
+  #[test]
+  fn division_by_zero() {
+    let zero = number(&[], 0.0);
+    let zero_id = zero.id;
+    let graph = graph(vec![zero]);
+
+    assert_eq!(eval(&graph, "1 / 0"), Err(FormulaError::DivisionByZero));
+    assert_eq!(
+      eval(&graph, "1 / (2 - 2)"),
+      Err(FormulaError::DivisionByZero)
+    );
+    assert_eq!(
+      eval(&graph, &format!("1 / cell:{}", zero_id)),
+      Err(FormulaError::DivisionByZero)
+    );
+    assert_eq!(eval(&graph, "0 / 1"), Ok(0.0));
+  }
+
+  fn is_too_deep(formula: &str) -> bool {
+    parse(formula) == Err(too_deep())
+  }
+
+  #[test]
+  fn formulas_nest_up_to_the_nesting_limit() {
+    let graph = graph(Vec::new());
+
+    // Every negation, operator and function call is a level, on top of the number.
+    assert_eq!(
+      eval(&graph, &format!("{}1", "-".repeat(MAX_NESTING - 1))),
+      Ok(-1.0)
+    );
+    assert_eq!(
+      eval(&graph, &format!("1{}", " + 1".repeat(MAX_NESTING - 1))),
+      Ok(MAX_NESTING as f64)
+    );
+    assert!(is_too_deep(&format!("{}1", "-".repeat(MAX_NESTING))));
+    assert!(is_too_deep(&format!("1{}", " + 1".repeat(MAX_NESTING))));
+    assert!(is_too_deep(&format!(
+      "{}1{}",
+      "SUM(".repeat(MAX_NESTING),
+      ")".repeat(MAX_NESTING)
+    )));
+  }
+
+  // These would overflow the stack if parsing recursed without a limit.
+  #[test]
+  fn deeply_nested_formulas_are_rejected() {
+    assert!(is_too_deep(&format!("{}1", "-".repeat(100_000))));
+    assert!(is_too_deep(&format!(
+      "{}1{}",
+      "(".repeat(100_000),
+      ")".repeat(100_000)
+    )));
+    assert!(is_too_deep(&format!("1{}", "*2".repeat(100_000))));
+  }
+
+  // A chain of `levels` formulas, each adding 1 to the one below it, on top of a 1.
+  fn chain(levels: usize) -> (FormulaGraph, Uuid) {
+    let mut cells = vec![number(&[], 1.0)];
+
+    for _ in 0..levels {
+      let below = cells[cells.len() - 1].id;
+      cells.push(formula(&[], &format!("cell:{} + 1", below)));
+    }
+
+    let top_id = cells[levels].id;
+
+    (graph(cells), top_id)
+  }
+
+  #[test]
+  fn formulas_nest_up_to_the_depth_limit() {
+    let (graph, top_id) = chain(MAX_DEPTH);
+
+    assert_eq!(eval_cell(&graph, top_id), Ok((MAX_DEPTH + 1) as f64));
+
+    let (graph, top_id) = chain(MAX_DEPTH + 1);
+
+    assert_eq!(eval_cell(&graph, top_id), Err(FormulaError::TooDeep));
+  }
+}
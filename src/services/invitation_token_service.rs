@@ -0,0 +1,147 @@
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use jsonwebtoken::errors::ErrorKind;
+use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use uuid::Uuid;
+
+use crate::errors::ApiError;
+
+pub const DEFAULT_INVITATION_TTL_HOURS: i64 = 7 * 24;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct InvitationClaims {
+  // Id of the invitation.
+  jti: Uuid,
+
+  exp: i64,
+}
+
+// Signs and verifies the tokens that invitation emails link with. A token only carries the id of
+// its invitation, whether it has been used is up to the invitations table.
+pub struct InvitationTokens {
+  secret: Vec<u8>,
+
+  pub ttl: Duration,
+}
+
+impl InvitationTokens {
+  // INVITATION_SECRET is the HS256 signing secret, INVITATION_TTL_HOURS defaults to a week.
+  pub fn from_env() -> std::result::Result<Self, String> {
+    InvitationTokens::parse(
+      std::env::var("INVITATION_SECRET")
+        .ok()
+        .as_deref(),
+      std::env::var("INVITATION_TTL_HOURS")
+        .ok()
+        .as_deref(),
+    )
+  }
+
+  fn parse(secret: Option<&str>, ttl_hours: Option<&str>) -> std::result::Result<Self, String> {
+    let secret = secret
+      .filter(|secret| !secret.is_empty())
+      .ok_or_else(|| String::from("INVITATION_SECRET is not set"))?;
+
+    let ttl_hours = match ttl_hours {
+      Some(hours) => hours
+        .parse::<i64>()
+        .ok()
+        .filter(|hours| *hours > 0)
+        .ok_or_else(|| {
+          format!(
+            "INVITATION_TTL_HOURS must be a positive integer, got \"{}\"",
+            hours
+          )
+        })?,
+      None => DEFAULT_INVITATION_TTL_HOURS,
+    };
+
+    Ok(InvitationTokens {
+      secret: secret
+        .as_bytes()
+        .to_vec(),
+      ttl: Duration::hours(ttl_hours),
+    })
+  }
+
+  pub fn expires_at(&self) -> DateTime<Utc> {
+    Utc::now() + self.ttl
+  }
+
+  pub fn sign(&self, invitation_id: Uuid, expires_at: DateTime<Utc>) -> Result<String> {
+    let claims = InvitationClaims {
+      jti: invitation_id,
+      exp: expires_at.timestamp(),
+    };
+
+    encode(
+      &Header::new(Algorithm::HS256),
+      &claims,
+      &EncodingKey::from_secret(&self.secret),
+    )
+    .map_err(anyhow::Error::from)
+  }
+
+  // Returns the id of the invitation the token was signed for.
+  pub fn verify(&self, token: &str) -> Result<Uuid> {
+    decode::<InvitationClaims>(
+      token,
+      &DecodingKey::from_secret(&self.secret),
+      &Validation::new(Algorithm::HS256),
+    )
+    .map(|data| data.claims.jti)
+    .map_err(|err| {
+      let msg = match err.kind() {
+        ErrorKind::ExpiredSignature => "Invitation has expired",
+        _ => "Invalid invitation token",
+      };
+
+      ApiError::Validation(String::from(msg)).raise()
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn ttl_defaults_to_a_week() {
+    let tokens = InvitationTokens::parse(Some("secret"), None).unwrap();
+
+    assert_eq!(tokens.ttl, Duration::hours(DEFAULT_INVITATION_TTL_HOURS));
+
+    let tokens = InvitationTokens::parse(Some("secret"), Some("48")).unwrap();
+
+    assert_eq!(tokens.ttl, Duration::hours(48));
+  }
+
+  #[test]
+  fn malformed_config_is_an_error() {
+    assert!(InvitationTokens::parse(None, None).is_err());
+    assert!(InvitationTokens::parse(Some(""), None).is_err());
+    assert!(InvitationTokens::parse(Some("secret"), Some("0")).is_err());
+    assert!(InvitationTokens::parse(Some("secret"), Some("a week")).is_err());
+  }
+
+  #[test]
+  fn tokens_verify_to_their_invitation() {
+    let tokens = InvitationTokens::parse(Some("secret"), None).unwrap();
+    let invitation_id = Uuid::new_v4();
+
+    let token = tokens
+      .sign(invitation_id, tokens.expires_at())
+      .unwrap();
+
+    assert_eq!(
+      tokens
+        .verify(&token)
+        .unwrap(),
+      invitation_id
+    );
+    assert!(InvitationTokens::parse(Some("other secret"), None)
+      .unwrap()
+      .verify(&token)
+      .is_err());
+  }
+}
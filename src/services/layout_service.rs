@@ -0,0 +1,334 @@
+use uuid::Uuid;
+
+use crate::graphql::schema::block::BBox;
+
+pub const DEFAULT_GRID_COLUMNS: i32 = 12;
+
+// How far down a portal view blocks can go, written or pushed.
+pub const MAX_GRID_ROWS: i32 = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OverlapStrategy {
+  // Writes that would make blocks overlap fail.
+  Reject,
+  // Blocks in the way of a write are pushed down below it.
+  PushDown,
+}
+
+// How blocks are laid out on the grid of a portal view.
+#[derive(Debug, Clone)]
+pub struct LayoutConfig {
+  pub columns: i32,
+
+  pub overlap: OverlapStrategy,
+}
+
+impl LayoutConfig {
+  // BLOCK_GRID_COLUMNS sets the width of the grid, BLOCK_OVERLAP is either "reject" or
+  // "push_down". Both are optional.
+  pub fn from_env() -> Result<Self, String> {
+    let config = LayoutConfig::parse(
+      std::env::var("BLOCK_GRID_COLUMNS")
+        .ok()
+        .as_deref(),
+      std::env::var("BLOCK_OVERLAP")
+        .ok()
+        .as_deref(),
+    )?;
+
+    println!(
+      "block layout: {} columns, {:?}",
+      config.columns, config.overlap
+    );
+
+    Ok(config)
+  }
+
+  fn parse(columns: Option<&str>, overlap: Option<&str>) -> Result<Self, String> {
+    let columns = match columns {
+      Some(columns) => columns
+        .parse::<i32>()
+        .ok()
+        .filter(|columns| *columns > 0)
+        .ok_or_else(|| {
+          format!(
+            "BLOCK_GRID_COLUMNS must be a positive integer, got \"{}\"",
+            columns
+          )
+        })?,
+      None => DEFAULT_GRID_COLUMNS,
+    };
+
+    let overlap = match overlap {
+      Some("push_down") => OverlapStrategy::PushDown,
+      Some("reject") | None => OverlapStrategy::Reject,
+      Some(other) => {
+        return Err(format!(
+          "BLOCK_OVERLAP must be \"reject\" or \"push_down\", got \"{}\"",
+          other
+        ))
+      }
+    };
+
+    Ok(LayoutConfig { columns, overlap })
+  }
+}
+
+pub fn check_bbox(bbox: &BBox, config: &LayoutConfig) -> Result<(), String> {
+  if bbox.w < 1 || bbox.h < 1 {
+    return Err(format!(
+      "bbox must be at least 1x1, got {}x{}",
+      bbox.w, bbox.h
+    ));
+  }
+
+  if bbox.x < 0 || bbox.y < 0 {
+    return Err(format!(
+      "bbox must not start at a negative position, got ({}, {})",
+      bbox.x, bbox.y
+    ));
+  }
+
+  if bbox.right() > config.columns {
+    return Err(format!(
+      "bbox must fit within the {} grid columns, got {} columns from column {}",
+      config.columns, bbox.w, bbox.x
+    ));
+  }
+
+  if bbox.bottom() > MAX_GRID_ROWS {
+    return Err(format!(
+      "bbox must fit within the {} grid rows, got {} rows from row {}",
+      MAX_GRID_ROWS, bbox.h, bbox.y
+    ));
+  }
+
+  Ok(())
+}
+
+// Places the `moved` blocks at their requested positions among the `others` blocks of the same
+// portal view. Returns the position of every block that has to be written: the moved blocks, plus
+// any of the others that were pushed out of the way.
+pub fn layout(
+  moved: &[(Uuid, BBox)],
+  others: &[(Uuid, BBox)],
+  config: &LayoutConfig,
+) -> Result<Vec<(Uuid, BBox)>, String> {
+  for (id, bbox) in moved {
+    check_bbox(bbox, config).map_err(|msg| format!("Block {}: {}", id, msg))?;
+  }
+
+  // Whatever the strategy, there's no telling which of two moved blocks should give way.
+  for (i, (id, bbox)) in moved
+    .iter()
+    .enumerate()
+  {
+    if let Some((other_id, _)) = moved[i + 1..]
+      .iter()
+      .find(|(_, other)| bbox.overlaps(other))
+    {
+      return Err(format!("Block {} overlaps block {}", id, other_id));
+    }
+  }
+
+  let mut changed = moved.to_vec();
+
+  match config.overlap {
+    OverlapStrategy::Reject => {
+      for (id, bbox) in others {
+        if let Some((moved_id, _)) = moved
+          .iter()
+          .find(|(_, moved_bbox)| moved_bbox.overlaps(bbox))
+        {
+          return Err(format!("Block {} overlaps block {}", moved_id, id));
+        }
+      }
+    }
+    OverlapStrategy::PushDown => {
+      let mut placed: Vec<BBox> = moved
+        .iter()
+        .map(|(_, bbox)| *bbox)
+        .collect();
+
+      // Top to bottom, so blocks keep their order down the page as they get pushed.
+      let mut others = others.to_vec();
+      others.sort_by_key(|(_, bbox)| (bbox.y, bbox.x));
+
+      for (id, bbox) in others {
+        let mut pushed = bbox;
+
+        while let Some(bottom) = placed
+          .iter()
+          .filter(|p| p.overlaps(&pushed))
+          .map(|p| p.bottom())
+          .max()
+        {
+          pushed.y = bottom;
+        }
+
+        if pushed.bottom() > MAX_GRID_ROWS {
+          return Err(format!(
+            "Block {} would be pushed past the {} grid rows",
+            id, MAX_GRID_ROWS
+          ));
+        }
+
+        if pushed != bbox {
+          changed.push((id, pushed));
+        }
+
+        placed.push(pushed);
+      }
+    }
+  }
+
+  Ok(changed)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn bbox(x: i32, y: i32, w: i32, h: i32) -> BBox {
+    BBox { x, y, w, h }
+  }
+
+  fn config(overlap: OverlapStrategy) -> LayoutConfig {
+    LayoutConfig {
+      columns: DEFAULT_GRID_COLUMNS,
+      overlap,
+    }
+  }
+
+  #[test]
+  fn bboxes_fit_within_the_columns() {
+    let config = config(OverlapStrategy::Reject);
+
+    assert_eq!(check_bbox(&bbox(0, 0, 12, 1), &config), Ok(()));
+    assert_eq!(check_bbox(&bbox(8, 40, 4, 1), &config), Ok(()));
+    assert!(check_bbox(&bbox(9, 0, 4, 1), &config).is_err());
+    assert!(check_bbox(&bbox(0, 0, 13, 1), &config).is_err());
+  }
+
+  #[test]
+  fn bboxes_have_a_size_and_a_position() {
+    let config = config(OverlapStrategy::Reject);
+
+    assert!(check_bbox(&bbox(0, 0, 0, 1), &config).is_err());
+    assert!(check_bbox(&bbox(0, 0, 1, 0), &config).is_err());
+    assert!(check_bbox(&bbox(-1, 0, 1, 1), &config).is_err());
+    assert!(check_bbox(&bbox(0, -1, 1, 1), &config).is_err());
+  }
+
+  #[test]
+  fn huge_bboxes_do_not_overflow() {
+    let config = config(OverlapStrategy::Reject);
+
+    assert!(check_bbox(&bbox(1, 0, i32::MAX, 1), &config).is_err());
+    assert!(check_bbox(&bbox(i32::MAX, 0, 1, 1), &config).is_err());
+    assert!(check_bbox(&bbox(0, 1, 1, i32::MAX), &config).is_err());
+    assert!(check_bbox(&bbox(0, i32::MAX, 1, 1), &config).is_err());
+    assert!(check_bbox(&bbox(0, MAX_GRID_ROWS - 1, 1, 1), &config).is_ok());
+
+    let huge = bbox(1, 1, i32::MAX, i32::MAX);
+
+    assert!(huge.overlaps(&bbox(i32::MAX - 1, i32::MAX - 1, 1, 1)));
+    assert!(!huge.overlaps(&bbox(0, 0, 1, 1)));
+    assert_eq!(huge.bottom(), i32::MAX);
+  }
+
+  #[test]
+  fn blocks_can_not_be_pushed_past_the_rows() {
+    let moved = [(Uuid::new_v4(), bbox(0, 0, 4, MAX_GRID_ROWS))];
+    let others = [(Uuid::new_v4(), bbox(0, 0, 4, 1))];
+
+    assert!(layout(&moved, &others, &config(OverlapStrategy::PushDown)).is_err());
+  }
+
+  #[test]
+  fn moves_outside_the_columns_fail() {
+    let moved = [(Uuid::new_v4(), bbox(10, 0, 4, 1))];
+
+    assert!(layout(&moved, &[], &config(OverlapStrategy::Reject)).is_err());
+    assert!(layout(&moved, &[], &config(OverlapStrategy::PushDown)).is_err());
+  }
+
+  #[test]
+  fn reject_fails_overlapping_moves() {
+    let moved = [(Uuid::new_v4(), bbox(0, 0, 4, 2))];
+    let others = [(Uuid::new_v4(), bbox(2, 1, 4, 2))];
+
+    assert!(layout(&moved, &others, &config(OverlapStrategy::Reject)).is_err());
+  }
+
+  #[test]
+  fn adjacent_edges_do_not_overlap() {
+    let moved = [(Uuid::new_v4(), bbox(0, 0, 4, 2))];
+    let others = [
+      (Uuid::new_v4(), bbox(4, 0, 4, 2)),
+      (Uuid::new_v4(), bbox(0, 2, 4, 2)),
+      (Uuid::new_v4(), bbox(4, 2, 4, 2)),
+    ];
+
+    for overlap in [OverlapStrategy::Reject, OverlapStrategy::PushDown].iter() {
+      assert_eq!(
+        layout(&moved, &others, &config(*overlap)),
+        Ok(moved.to_vec())
+      );
+    }
+  }
+
+  #[test]
+  fn push_down_moves_blocks_below_the_written_one() {
+    let moved = [(Uuid::new_v4(), bbox(0, 0, 6, 3))];
+    let beside = (Uuid::new_v4(), bbox(8, 0, 4, 2));
+    let under = (Uuid::new_v4(), bbox(2, 1, 4, 2));
+    let below_under = (Uuid::new_v4(), bbox(2, 3, 4, 2));
+
+    let changed = layout(
+      &moved,
+      &[below_under, beside, under],
+      &config(OverlapStrategy::PushDown),
+    );
+
+    assert_eq!(
+      changed,
+      Ok(vec![
+        moved[0],
+        (under.0, bbox(2, 3, 4, 2)),
+        (below_under.0, bbox(2, 5, 4, 2)),
+      ])
+    );
+  }
+
+  #[test]
+  fn moved_blocks_can_not_overlap_each_other() {
+    let moved = [
+      (Uuid::new_v4(), bbox(0, 0, 4, 2)),
+      (Uuid::new_v4(), bbox(3, 1, 4, 2)),
+    ];
+
+    assert!(layout(&moved, &[], &config(OverlapStrategy::Reject)).is_err());
+    assert!(layout(&moved, &[], &config(OverlapStrategy::PushDown)).is_err());
+  }
+
+  #[test]
+  fn config_defaults() {
+    let config = LayoutConfig::parse(None, None).unwrap();
+
+    assert_eq!(config.columns, DEFAULT_GRID_COLUMNS);
+    assert_eq!(config.overlap, OverlapStrategy::Reject);
+
+    let config = LayoutConfig::parse(Some("24"), Some("push_down")).unwrap();
+
+    assert_eq!(config.columns, 24);
+    assert_eq!(config.overlap, OverlapStrategy::PushDown);
+  }
+
+  #[test]
+  fn malformed_config_is_an_error() {
+    assert!(LayoutConfig::parse(Some("0"), None).is_err());
+    assert!(LayoutConfig::parse(Some("twelve"), None).is_err());
+    assert!(LayoutConfig::parse(None, Some("sideways")).is_err());
+  }
+}
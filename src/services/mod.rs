@@ -1,3 +1,8 @@
 pub mod auth0_service;
 pub mod email_service;
-pub mod db;
\ No newline at end of file
+pub mod db;
+pub mod jwks_service;
+pub mod change_broker;
+pub mod formula_service;
+pub mod layout_service;
+pub mod invitation_token_service;
\ No newline at end of file